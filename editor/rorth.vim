@@ -33,6 +33,9 @@ syntax region rorthNumber start=/\d/ skip=/\d/ end=/\s/
 
 " Type names the compiler recognizes
 syntax keyword rorthTypeNames addr int ptr bool
+
+" Bool literals
+syntax keyword rorthBoolean true false
 " Set highlights
 highlight default link rorthTodos Todo
 highlight default link rorthKeywords Keyword
@@ -40,6 +43,7 @@ highlight default link rorthCommentLine Comment
 highlight default link rorthString String
 highlight default link rorthNumber Number
 highlight default link rorthTypeNames Type
+highlight default link rorthBoolean Boolean
 highlight default link rorthChar Character
 highlight default link rorthEscapes SpecialChar
 
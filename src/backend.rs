@@ -0,0 +1,244 @@
+use std::{
+    env,
+    fs::File,
+    io::{LineWriter, Write},
+    process::Command,
+};
+
+use crate::{runtime_error_message, Op, OpKind, OpValue, RuntimeCheck};
+
+mod arm64;
+mod x86_64;
+
+pub use arm64::{Arm64, Arm64Os};
+pub use x86_64::X86_64;
+
+/// A native code generator for one platform. `compile_program` drives it over
+/// the whole program and defines the label `addr_{ip}` in front of every op,
+/// so a backend only needs to know how to lower single ops.
+pub trait Backend {
+    /// Runtime helpers and the program entry point.
+    fn emit_prologue(&self, file: &mut dyn Write);
+    fn emit_op(&self, file: &mut dyn Write, op: &Op, ip: usize, data: &Data);
+    /// Exit of the program followed by its data section.
+    fn emit_epilogue(&self, file: &mut dyn Write, data: &Data);
+    fn assemble(&self, filename_pre: &str, silence_flag: bool) -> bool;
+    fn link(&self, filename_pre: &str, silence_flag: bool) -> bool;
+    /// Program the linked executable has to be run through, if any.
+    fn runner(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Everything the program refers to by label, numbered in order of first use.
+pub struct Data<'a> {
+    pub strings: Vec<&'a Op>,
+    /// Name and size of every `memory` region.
+    pub memories: Vec<(&'a str, u64)>,
+    /// Whether to emit the runtime checks of `--checked` mode.
+    pub checked: bool,
+    /// Label and error message of every runtime check, the return stack of
+    /// procedures is checked even without `--checked`.
+    pub checks: Vec<(String, String)>,
+}
+
+impl<'a> Data<'a> {
+    fn new(program: &'a [Op], checked: bool) -> Self {
+        let strings = program
+            .iter()
+            .filter(|op| op.kind == OpKind::Push)
+            .collect();
+        let mut memories: Vec<(&str, u64)> = vec![];
+        for op in program {
+            if let Some(OpValue::MemoryRegion { name, size }) = &op.value {
+                if !memories.iter().any(|(memory, _)| memory == name) {
+                    memories.push((name, *size));
+                }
+            }
+        }
+        let mut checks = vec![];
+        for (ip, op) in program.iter().enumerate() {
+            if checked {
+                for check in RuntimeCheck::for_kind(op.kind) {
+                    let message = runtime_error_message(op, check.description());
+                    checks.push((check_label(ip, *check), format!("{message}\n")));
+                }
+            }
+            if op.kind == OpKind::Fn {
+                let message = runtime_error_message(op, "return stack overflow");
+                checks.push((ret_stack_overflow_label(ip), format!("{message}\n")));
+            }
+        }
+        Self {
+            strings,
+            memories,
+            checked,
+            checks,
+        }
+    }
+
+    pub fn memory_index(&self, name: &str) -> Option<usize> {
+        self.memories.iter().position(|(memory, _)| *memory == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    LinuxX86_64,
+    LinuxAarch64,
+    DarwinArm64,
+}
+
+impl Target {
+    /// Accepts the usual `<arch>-<vendor>-<os>[-<env>]` triples, e.g.
+    /// `x86_64-unknown-linux-gnu` or `aarch64-apple-darwin`.
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        let mut parts = triple.split('-');
+        let arch = parts.next()?;
+        let rest: Vec<&str> = parts.collect();
+        let linux = rest.contains(&"linux");
+        let darwin = rest
+            .iter()
+            .any(|part| matches!(*part, "apple" | "darwin" | "macos"));
+        match arch {
+            "x86_64" if linux => Some(Self::LinuxX86_64),
+            "aarch64" | "arm64" if linux => Some(Self::LinuxAarch64),
+            "aarch64" | "arm64" if darwin => Some(Self::DarwinArm64),
+            _ => None,
+        }
+    }
+
+    pub fn host() -> Option<Self> {
+        Self::from_triple(&format!("{}-{}", env::consts::ARCH, env::consts::OS))
+    }
+
+    pub fn backend(self) -> Box<dyn Backend> {
+        match self {
+            Self::LinuxX86_64 => Box::new(X86_64),
+            Self::LinuxAarch64 => Box::new(Arm64 { os: Arm64Os::Linux }),
+            Self::DarwinArm64 => Box::new(Arm64 {
+                os: Arm64Os::Darwin,
+            }),
+        }
+    }
+
+    /// The syscall numbers and `open` flags of the target. Programs get them
+    /// as constants and the simulator emulates the syscalls by these numbers.
+    pub const fn constants(self) -> &'static [(&'static str, i64)] {
+        match self {
+            Self::LinuxX86_64 => &[
+                ("SYS_read", 0),
+                ("SYS_write", 1),
+                ("SYS_open", 2),
+                ("SYS_close", 3),
+                ("SYS_getpid", 39),
+                ("SYS_exit", 60),
+                ("SYS_openat", 257),
+                ("O_RDONLY", 0),
+                ("O_WRONLY", 1),
+                ("O_RDWR", 2),
+                ("O_CREAT", 0o100),
+                ("O_TRUNC", 0o1000),
+                ("O_APPEND", 0o2000),
+                ("AT_FDCWD", -100),
+            ],
+            // Like every newer Linux port aarch64 only has `openat`.
+            Self::LinuxAarch64 => &[
+                ("SYS_openat", 56),
+                ("SYS_close", 57),
+                ("SYS_read", 63),
+                ("SYS_write", 64),
+                ("SYS_exit", 93),
+                ("SYS_getpid", 172),
+                ("O_RDONLY", 0),
+                ("O_WRONLY", 1),
+                ("O_RDWR", 2),
+                ("O_CREAT", 0o100),
+                ("O_TRUNC", 0o1000),
+                ("O_APPEND", 0o2000),
+                ("AT_FDCWD", -100),
+            ],
+            Self::DarwinArm64 => &[
+                ("SYS_exit", 1),
+                ("SYS_read", 3),
+                ("SYS_write", 4),
+                ("SYS_open", 5),
+                ("SYS_close", 6),
+                ("SYS_getpid", 20),
+                ("SYS_openat", 463),
+                ("O_RDONLY", 0),
+                ("O_WRONLY", 1),
+                ("O_RDWR", 2),
+                ("O_CREAT", 0x200),
+                ("O_TRUNC", 0x400),
+                ("O_APPEND", 0x8),
+                ("AT_FDCWD", -2),
+            ],
+        }
+    }
+
+    pub fn constant(self, name: &str) -> Option<i64> {
+        self.constants()
+            .iter()
+            .find(|(constant, _)| *constant == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// The label of the code that reports a failed `check` of the op at `ip`.
+pub fn check_label(ip: usize, check: RuntimeCheck) -> String {
+    match check {
+        RuntimeCheck::DivisionByZero => format!("check{ip}_division_by_zero"),
+        RuntimeCheck::Overflow => format!("check{ip}_overflow"),
+    }
+}
+
+/// The label of the code that reports a return stack overflow on entering
+/// the procedure defined at `ip`.
+pub fn ret_stack_overflow_label(ip: usize) -> String {
+    format!("ret_stack_overflow_{ip}")
+}
+
+/// `.byte` directives that emit exactly `bytes`, without any escaping.
+pub fn byte_directives(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .map(|chunk| {
+            let chunk: Vec<String> = chunk.iter().map(ToString::to_string).collect();
+            format!("        .byte {}\n", chunk.join(", "))
+        })
+        .collect()
+}
+
+pub fn compile_program(program: &[Op], filename: &str, backend: &dyn Backend, checked: bool) {
+    let file = File::create(format!("{filename}.s"));
+    if let Ok(file) = file {
+        let mut file = LineWriter::new(file);
+        let data = Data::new(program, checked);
+        backend.emit_prologue(&mut file);
+        // Every op starts at `addr_{ip}`, the target of all jumps to it.
+        for (ip, op) in program.iter().enumerate() {
+            let _ = file.write(format!("addr_{ip}:\n").as_bytes());
+            backend.emit_op(&mut file, op, ip, &data);
+            let _ = file.write(b"\n");
+        }
+        let _ = file.write(format!("addr_{}:\n", program.len()).as_bytes());
+        backend.emit_epilogue(&mut file, &data);
+    }
+}
+
+/// Runs an assembler or linker step, echoing it unless silenced.
+fn run_command(cmd: &mut Command, silence_flag: bool) -> bool {
+    if !silence_flag {
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        println!(
+            "[CMD] {} {}",
+            cmd.get_program().to_string_lossy(),
+            args.join(" ")
+        );
+    }
+    cmd.status().is_ok_and(|status| status.success())
+}
@@ -0,0 +1,611 @@
+use std::{env, io::Write, process::Command};
+
+use super::{byte_directives, check_label, ret_stack_overflow_label, run_command, Backend, Data};
+use crate::{Op, OpKind, OpValue, RuntimeCheck, MEMORY_ALIGNMENT, MEM_CAPACITY, RET_STACK_DEPTH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Syscall {
+    Write,
+    Exit,
+}
+
+/// Branches to the overflow check of the op at `ip` if `branch` is taken.
+fn emit_overflow_check(file: &mut dyn Write, branch: &str, ip: usize, data: &Data) {
+    if data.checked {
+        let label = check_label(ip, RuntimeCheck::Overflow);
+        let _ = file.write(format!("    {branch} {label}\n").as_bytes());
+    }
+}
+
+/// Checks the divisor in x0 and the dividend in x1 before a division. `sdiv`
+/// itself never traps, it gives 0 for a zero divisor and MIN for MIN / -1.
+fn emit_division_checks(file: &mut dyn Write, op: &Op, ip: usize, data: &Data) {
+    if !data.checked {
+        return;
+    }
+    let label = check_label(ip, RuntimeCheck::DivisionByZero);
+    let _ = file.write(format!("    cbz x0, {label}\n").as_bytes());
+    if RuntimeCheck::for_kind(op.kind).contains(&RuntimeCheck::Overflow) {
+        // Z is only set if x1 is MIN and x0 is -1.
+        let _ = file.write(b"    mov x4, #0x8000000000000000\n");
+        let _ = file.write(b"    cmp x1, x4\n");
+        let _ = file.write(b"    ccmn x0, #1, #0, eq\n");
+        emit_overflow_check(file, "b.eq", ip, data);
+    }
+}
+
+/// The OS specific pieces of the arm64 backend. Everything else
+/// `Arm64` emits is plain `AArch64` shared by all of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arm64Os {
+    Darwin,
+    Linux,
+}
+
+impl Arm64Os {
+    fn load_address(self, reg: &str, label: &str) -> String {
+        match self {
+            Self::Darwin => {
+                format!("    adrp {reg}, {label}@PAGE\n    add {reg}, {reg}, {label}@PAGEOFF\n")
+            }
+            Self::Linux => {
+                format!("    adrp {reg}, {label}\n    add {reg}, {reg}, :lo12:{label}\n")
+            }
+        }
+    }
+
+    const fn syscall_number(self, syscall: Syscall) -> u64 {
+        match (self, syscall) {
+            (Self::Darwin, Syscall::Write) => 4,
+            (Self::Darwin, Syscall::Exit) => 1,
+            (Self::Linux, Syscall::Write) => 64,
+            (Self::Linux, Syscall::Exit) => 93,
+        }
+    }
+
+    /// The register the syscall number goes in and the instruction that
+    /// traps into the kernel.
+    const fn syscall_abi(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Darwin => ("x16", "svc #0x80"),
+            Self::Linux => ("x8", "svc #0"),
+        }
+    }
+
+    fn syscall(self, syscall: Syscall) -> String {
+        let number = self.syscall_number(syscall);
+        let (reg, trap) = self.syscall_abi();
+        format!("    mov {reg}, #{number}\n    {trap}\n")
+    }
+}
+
+pub struct Arm64 {
+    pub os: Arm64Os,
+}
+
+impl Backend for Arm64 {
+    fn emit_prologue(&self, file: &mut dyn Write) {
+        let os = self.os;
+        let _ = file.write(b".global _start\n");
+        let _ = file.write(b".align 2\n\n");
+        let _ = file.write(b".text\n");
+        let _ = file.write(b"print:\n");
+        let _ = file.write(os.load_address("x0", "num").as_bytes());
+        let _ = file.write(b"    ldr   x1, [sp], #16\n");
+        let _ = file.write(b"    mov x2, #10\n");
+        let _ = file.write(b"    mov x3, #19\n");
+        // Convert the magnitude, the sign is written in front of it afterwards.
+        let _ = file.write(b"    mov x7, x1\n");
+        let _ = file.write(b"    cmp x1, #0\n");
+        let _ = file.write(b"    b.ge convert_loop\n");
+        let _ = file.write(b"    neg x1, x1\n");
+        let _ = file.write(b"convert_loop:\n");
+        let _ = file.write(b"    udiv x4, x1, x2\n");
+        let _ = file.write(b"    mul x5, x4, x2\n");
+        let _ = file.write(b"    sub x6, x1, x5 \n");
+        let _ = file.write(b"    and w6, w6, #0xFF\n");
+        let _ = file.write(b"    add x6, x6, #'0'\n");
+        let _ = file.write(b"    strb w6, [x0, x3]\n");
+        let _ = file.write(b"    sub x3, x3, #1\n");
+        let _ = file.write(b"    mov x1, x4\n");
+        let _ = file.write(b"    cmp x1, #0\n");
+        let _ = file.write(b"    bne convert_loop\n");
+        let _ = file.write(b"    cmp x7, #0\n");
+        let _ = file.write(b"    b.ge print_digits\n");
+        let _ = file.write(b"    mov w6, #'-'\n");
+        let _ = file.write(b"    strb w6, [x0, x3]\n");
+        let _ = file.write(b"print_digits:\n");
+        let _ = file.write(os.load_address("x4", "num").as_bytes());
+        let _ = file.write(b"    mov x1, x4\n");
+        let _ = file.write(b"    mov x0, #1\n");
+        let _ = file.write(b"    mov x2, #20\n");
+        let _ = file.write(os.syscall(Syscall::Write).as_bytes());
+        let _ = file.write(os.load_address("x0", "newline").as_bytes());
+        let _ = file.write(b"    mov x1, x0\n");
+        let _ = file.write(b"    mov x0, #1\n");
+        let _ = file.write(b"    mov x2, #1\n");
+        let _ = file.write(os.syscall(Syscall::Write).as_bytes());
+        let _ = file.write(b"	mov x1, #20\n");
+        let _ = file.write(b"loop:\n");
+        let _ = file.write(b"	strb wzr, [x4], #1\n");
+        let _ = file.write(b"	subs x1, x1, #1\n");
+        let _ = file.write(b"	bne loop\n");
+        let _ = file.write(b"	ret\n\n");
+        let _ = file.write(b"_start: \n");
+        let _ = file.write(b"    // x28 is the return stack pointer\n");
+        let _ = file.write(os.load_address("x28", "ret_stack_end").as_bytes());
+        let _ = file.write(b"\n");
+    }
+
+    fn emit_op(&self, file: &mut dyn Write, op: &Op, ip: usize, data: &Data) {
+        let os = self.os;
+        use OpValue::{BoolVal, IntVal, MemoryRegion, Proc, StringVal};
+        match op.kind {
+            OpKind::Push => {
+                if let Some(IntVal(val)) = &op.value {
+                    let _ = file.write(b"    // push \n");
+                    let _ = file.write(format!("    ldr x0, ={val}\n").as_bytes());
+                    let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                } else if let Some(BoolVal(val)) = &op.value {
+                    let _ = file.write(b"    // push \n");
+                    let _ = file.write(format!("    mov x0, #{}\n", u8::from(*val)).as_bytes());
+                    let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                } else if let Some(StringVal(val)) = &op.value {
+                    let val_idx = data
+                        .strings
+                        .iter()
+                        .position(|op| op.value == Some(OpValue::StringVal(val.clone())));
+                    if let Some(idx) = val_idx {
+                        let _ = file.write(b"    // push \n");
+                        let _ = file.write(format!("    ldr x0, ={}\n", val.len()).as_bytes());
+                        let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                        let _ =
+                            file.write(os.load_address("x0", &format!("string{idx}")).as_bytes());
+                        let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                    }
+                }
+            }
+            OpKind::Plus => {
+                let _ = file.write(b"    // plus \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    adds x3, x0, x1\n");
+                emit_overflow_check(file, "b.vs", ip, data);
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Minus => {
+                let _ = file.write(b"    // minus \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    subs x3, x1, x0\n");
+                emit_overflow_check(file, "b.vs", ip, data);
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Mult => {
+                let _ = file.write(b"    // mult \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    mul x3, x1, x0\n");
+                if data.checked {
+                    // The high half has to be the sign extension of the low one.
+                    let _ = file.write(b"    smulh x4, x1, x0\n");
+                    let _ = file.write(b"    cmp x4, x3, asr #63\n");
+                    emit_overflow_check(file, "b.ne", ip, data);
+                }
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Div => {
+                let _ = file.write(b"    // div \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                emit_division_checks(file, op, ip, data);
+                let _ = file.write(b"    sdiv x3, x1, x0\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Mod => {
+                let _ = file.write(b"    // mod \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                emit_division_checks(file, op, ip, data);
+                let _ = file.write(b"    sdiv x2, x1, x0\n");
+                let _ = file.write(b"    msub x3, x2, x0, x1\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::DivMod => {
+                let _ = file.write(b"    // divmod \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                emit_division_checks(file, op, ip, data);
+                let _ = file.write(b"    sdiv x2, x1, x0\n");
+                let _ = file.write(b"    msub x3, x2, x0, x1\n");
+                let _ = file.write(b"    str x2, [sp, #-16]!\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Shl => {
+                let _ = file.write(b"    // shl \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    lsl x3, x1, x0\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Shr => {
+                let _ = file.write(b"    // shr \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    lsr x3, x1, x0\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Bor => {
+                let _ = file.write(b"    // bor \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    orr x3, x1, x0\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Band => {
+                let _ = file.write(b"    // band \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    and x3, x1, x0\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Bxor => {
+                let _ = file.write(b"    // bxor \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    eor x3, x1, x0\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Bnot => {
+                let _ = file.write(b"    // bnot \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    mvn x0, x0\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Equals => {
+                let _ = file.write(b"    // equals \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    cmp x1, x0\n");
+                let _ = file.write(b"    cset w0, EQ\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::NotEquals => {
+                let _ = file.write(b"    // not equals \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    cmp x1, x0\n");
+                let _ = file.write(b"    cset w0, NE\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Dup => {
+                let _ = file.write(b"    // dup \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Swap => {
+                let _ = file.write(b"    // swap \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                let _ = file.write(b"    str x1, [sp, #-16]!\n");
+            }
+            OpKind::Rot => {
+                let _ = file.write(b"    // rot \n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    ldr x2, [sp], #16\n");
+                let _ = file.write(b"    ldr x3, [sp], #16\n");
+                let _ = file.write(b"    str x2, [sp, #-16]!\n");
+                let _ = file.write(b"    str x1, [sp, #-16]!\n");
+                let _ = file.write(b"    str x3, [sp, #-16]!\n");
+            }
+            OpKind::Drop => {
+                let _ = file.write(b"    // drop \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+            }
+            OpKind::Print => {
+                let _ = file.write(b"    // print \n");
+                let _ = file.write(b"    bl print\n");
+            }
+            OpKind::Write => {
+                let _ = file.write(b"    // write \n");
+                let _ = file.write(b"    ldr	X0, [sp], #16\n");
+                let _ = file.write(b"    ldr	X1, [sp], #16\n");
+                let _ = file.write(b"    ldr	X2, [sp], #16\n");
+                let _ = file.write(os.syscall(Syscall::Write).as_bytes());
+            }
+            OpKind::Syscall0
+            | OpKind::Syscall1
+            | OpKind::Syscall2
+            | OpKind::Syscall3
+            | OpKind::Syscall4
+            | OpKind::Syscall5
+            | OpKind::Syscall6 => {
+                let args = op.kind.syscall_args().unwrap_or_default();
+                let (reg, trap) = os.syscall_abi();
+                let _ = file.write(format!("    // syscall{args} \n").as_bytes());
+                let _ = file.write(format!("    ldr {reg}, [sp], #16\n").as_bytes());
+                for arg in 0..args {
+                    let _ = file.write(format!("    ldr x{arg}, [sp], #16\n").as_bytes());
+                }
+                let _ = file.write(format!("    {trap}\n").as_bytes());
+                if os == Arm64Os::Darwin {
+                    // Darwin signals failure with the carry flag and a positive
+                    // errno, return -errno like Linux does.
+                    let _ = file.write(b"    b.cc 1f\n");
+                    let _ = file.write(b"    neg x0, x0\n");
+                    let _ = file.write(b"1:\n");
+                }
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Over => {
+                let _ = file.write(b"    // over \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    str x1, [sp, #-16]!\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                let _ = file.write(b"    str x1, [sp, #-16]!\n");
+            }
+            OpKind::If => {
+                let _ = file.write(b"    // if \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    cmp x0, #0\n");
+                if let Some(IntVal(ind)) = op.value {
+                    let _ = file.write(format!("    beq addr_{ind}\n").as_bytes());
+                }
+            }
+            OpKind::Else => {
+                let _ = file.write(b"    // else \n");
+                if let Some(IntVal(ind)) = op.value {
+                    let _ = file.write(format!("    b addr_{ind}\n").as_bytes());
+                }
+            }
+            OpKind::Exit => {
+                let _ = file.write(b"    // exit \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(os.syscall(Syscall::Exit).as_bytes());
+            }
+            OpKind::Mem => {
+                let _ = file.write(b"    // mem \n");
+                let _ = file.write(os.load_address("x0", "mem").as_bytes());
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Memory => {
+                if let Some(MemoryRegion { name, .. }) = &op.value {
+                    if let Some(idx) = data.memory_index(name) {
+                        let _ = file.write(format!("    // memory {name} \n").as_bytes());
+                        let _ =
+                            file.write(os.load_address("x0", &format!("memory{idx}")).as_bytes());
+                        let _ = file.write(b"    str x0, [sp, #-16]!\n");
+                    }
+                }
+            }
+            OpKind::Load8 => {
+                let _ = file.write(b"    // @8 \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldrb w1, [x0]\n");
+                let _ = file.write(b"    str x1, [sp, #-16]!\n");
+            }
+            OpKind::Store8 => {
+                let _ = file.write(b"    // !8 \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    strb w1, [x0]\n");
+            }
+            OpKind::Load64 => {
+                let _ = file.write(b"    // @64 \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [x0]\n");
+                let _ = file.write(b"    str x1, [sp, #-16]!\n");
+            }
+            OpKind::Store64 => {
+                let _ = file.write(b"    // !64 \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    str x1, [x0]\n");
+            }
+            OpKind::Fn => {
+                if let Some(Proc { end, .. }) = &op.value {
+                    let _ = file.write(format!("    b addr_{}\n", end + 1).as_bytes());
+                }
+                let _ = file.write(format!("proc_{ip}:\n").as_bytes());
+                let _ = file.write(os.load_address("x1", "ret_stack").as_bytes());
+                let _ = file.write(b"    cmp x28, x1\n");
+                let label = ret_stack_overflow_label(ip);
+                let _ = file.write(format!("    b.ls {label}\n").as_bytes());
+                let _ = file.write(b"    str x30, [x28, #-16]!\n");
+            }
+            OpKind::Call => {
+                if let Some(IntVal(fn_ip)) = op.value {
+                    let _ = file.write(b"    // call \n");
+                    let _ = file.write(format!("    bl proc_{fn_ip}\n").as_bytes());
+                }
+            }
+            OpKind::Ret => {
+                let _ = file.write(b"    // ret \n");
+                let _ = file.write(b"    ldr x30, [x28], #16\n");
+                let _ = file.write(b"    ret\n");
+            }
+            OpKind::While => {
+                let _ = file.write(b"    // while \n");
+            }
+            OpKind::Do => {
+                let _ = file.write(b"    // do \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    cmp x0, #0\n");
+                if let Some(IntVal(ind)) = op.value {
+                    let _ = file.write(format!("    beq addr_{ind}\n").as_bytes());
+                }
+            }
+            OpKind::End => {
+                if let Some(IntVal(ind)) = op.value {
+                    if let Ok(ind) = TryInto::<usize>::try_into(ind) {
+                        if ind != ip {
+                            let _ = file.write(format!("    b addr_{ind}\n").as_bytes());
+                        }
+                    }
+                }
+            }
+            OpKind::GT => {
+                let _ = file.write(b"    // > \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    cmp x1, x0\n");
+                let _ = file.write(b"    cset w0, GT\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::LT => {
+                let _ = file.write(b"    // < \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    cmp x1, x0\n");
+                let _ = file.write(b"    cset w0, LT\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::GE => {
+                let _ = file.write(b"    // >= \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    cmp x1, x0\n");
+                let _ = file.write(b"    cset w0, GE\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::LE => {
+                let _ = file.write(b"    // <= \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    cmp x1, x0\n");
+                let _ = file.write(b"    cset w0, LE\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::And => {
+                let _ = file.write(b"    // and \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    and x0, x1, x0\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Or => {
+                let _ = file.write(b"    // or \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    ldr x1, [sp], #16\n");
+                let _ = file.write(b"    orr x0, x1, x0\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+            OpKind::Not => {
+                let _ = file.write(b"    // not \n");
+                let _ = file.write(b"    ldr x0, [sp], #16\n");
+                let _ = file.write(b"    eor x0, x0, #1\n");
+                let _ = file.write(b"    str x0, [sp, #-16]!\n");
+            }
+        }
+    }
+
+    fn emit_epilogue(&self, file: &mut dyn Write, data: &Data) {
+        let os = self.os;
+        let _ = file.write(b"    // exit syscall\n");
+        let _ = file.write(b"    mov x0, #0\n");
+        let _ = file.write(os.syscall(Syscall::Exit).as_bytes());
+        let _ = file.write(b"\n");
+        if !data.checks.is_empty() {
+            let _ = file.write(b"// writes the message at x1 of length x2 and exits with 1\n");
+            let _ = file.write(b"runtime_error:\n");
+            let _ = file.write(b"    mov x0, #2\n");
+            let _ = file.write(os.syscall(Syscall::Write).as_bytes());
+            let _ = file.write(b"    mov x0, #1\n");
+            let _ = file.write(os.syscall(Syscall::Exit).as_bytes());
+            for (label, message) in &data.checks {
+                let _ = file.write(format!("{label}:\n").as_bytes());
+                let _ = file.write(
+                    os.load_address("x1", &format!("{label}_message"))
+                        .as_bytes(),
+                );
+                let _ = file.write(format!("    ldr x2, ={}\n", message.len()).as_bytes());
+                let _ = file.write(b"    b runtime_error\n");
+            }
+            let _ = file.write(b"\n");
+        }
+        let _ = file.write(b".data\n");
+        let _ = file.write(b"    num: .zero 20\n");
+        let _ = file.write(b"    newline: .asciz \"\\n\" \n");
+        for (idx, string) in data.strings.iter().enumerate() {
+            if let Some(OpValue::StringVal(val)) = &string.value {
+                let _ = file.write(format!("    string{idx}:\n").as_bytes());
+                let _ = file.write(byte_directives(val).as_bytes());
+                let _ = file.write(b"        .byte 0\n");
+            }
+        }
+        for (label, message) in &data.checks {
+            let _ = file.write(format!("    {label}_message:\n").as_bytes());
+            let _ = file.write(byte_directives(message.as_bytes()).as_bytes());
+        }
+        let _ = file.write(b".bss\n");
+        let _ = file.write(b"    .align 4\n");
+        let _ = file.write(format!("    ret_stack: .zero {}\n", RET_STACK_DEPTH * 16).as_bytes());
+        let _ = file.write(b"    ret_stack_end:\n");
+        let _ = file.write(format!("    .balign {MEMORY_ALIGNMENT}\n").as_bytes());
+        let _ = file.write(format!("    mem: .zero {MEM_CAPACITY}\n").as_bytes());
+        for (idx, (name, size)) in data.memories.iter().enumerate() {
+            let _ = file.write(format!("    .balign {MEMORY_ALIGNMENT}\n").as_bytes());
+            let _ = file.write(format!("    memory{idx}: .zero {size} // {name}\n").as_bytes());
+        }
+    }
+
+    fn assemble(&self, filename_pre: &str, silence_flag: bool) -> bool {
+        let mut cmd = match self.os {
+            Arm64Os::Darwin => {
+                let mut cmd = Command::new("as");
+                cmd.arg("-arch").arg("arm64");
+                cmd
+            }
+            Arm64Os::Linux if env::consts::ARCH == "aarch64" => Command::new("as"),
+            Arm64Os::Linux => Command::new("aarch64-linux-gnu-as"),
+        };
+        cmd.arg("-o")
+            .arg(format!("{filename_pre}.o"))
+            .arg(format!("{filename_pre}.s"));
+        run_command(&mut cmd, silence_flag)
+    }
+
+    fn link(&self, filename_pre: &str, silence_flag: bool) -> bool {
+        let mut cmd = match self.os {
+            Arm64Os::Darwin => {
+                let mut cmd = Command::new("ld");
+                cmd.arg("-L")
+                    .arg("/Library/Developer/CommandLineTools/SDKs/MacOSX.sdk/usr/lib")
+                    .arg("-lSystem")
+                    .arg("-syslibroot")
+                    .arg("`xcrun -sdk macosx --show-sdk-path`")
+                    .arg("-arch")
+                    .arg("arm64");
+                cmd
+            }
+            Arm64Os::Linux if env::consts::ARCH == "aarch64" => {
+                let mut cmd = Command::new("ld");
+                cmd.arg("-static");
+                cmd
+            }
+            Arm64Os::Linux => {
+                let mut cmd = Command::new("aarch64-linux-gnu-ld");
+                cmd.arg("-static");
+                cmd
+            }
+        };
+        cmd.arg("-o")
+            .arg(filename_pre)
+            .arg(format!("{filename_pre}.o"))
+            .arg("-e")
+            .arg("_start");
+        run_command(&mut cmd, silence_flag)
+    }
+
+    fn runner(&self) -> Option<&'static str> {
+        // Off an aarch64 host Linux binaries are cross built and run under qemu-user.
+        if self.os == Arm64Os::Linux && env::consts::ARCH != "aarch64" {
+            Some("qemu-aarch64")
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::{io::Write, process::Command};
+
+use super::{byte_directives, check_label, ret_stack_overflow_label, run_command, Backend, Data};
+use crate::{Op, OpKind, OpValue, RuntimeCheck, MEMORY_ALIGNMENT, MEM_CAPACITY, RET_STACK_DEPTH};
+
+pub struct X86_64;
+
+/// The registers the arguments of a syscall are passed in.
+const SYSCALL_ARGS: [&str; 6] = ["rdi", "rsi", "rdx", "r10", "r8", "r9"];
+
+/// Jumps to the overflow check of the op at `ip` if the last instruction
+/// overflowed.
+fn emit_overflow_check(file: &mut dyn Write, ip: usize, data: &Data) {
+    if data.checked {
+        let label = check_label(ip, RuntimeCheck::Overflow);
+        let _ = file.write(format!("    jo {label}\n").as_bytes());
+    }
+}
+
+/// Divides rax by rbx and leaves the quotient in rax and the remainder in rdx.
+/// `idiv` faults where the other backends don't: dividing by zero gives 0 with
+/// rax as the remainder and dividing MIN by -1 wraps around to MIN.
+fn emit_division(file: &mut dyn Write, op: &Op, ip: usize, data: &Data) {
+    if data.checked {
+        let _ = file.write(b"    test rbx, rbx\n");
+        let label = check_label(ip, RuntimeCheck::DivisionByZero);
+        let _ = file.write(format!("    jz {label}\n").as_bytes());
+        if RuntimeCheck::for_kind(op.kind).contains(&RuntimeCheck::Overflow) {
+            // rcx ends up 0 exactly for MIN / -1.
+            let _ = file.write(b"    movabs rcx, 0x8000000000000000\n");
+            let _ = file.write(b"    xor rcx, rax\n");
+            let _ = file.write(b"    mov rdx, rbx\n");
+            let _ = file.write(b"    not rdx\n");
+            let _ = file.write(b"    or rcx, rdx\n");
+            let label = check_label(ip, RuntimeCheck::Overflow);
+            let _ = file.write(format!("    jz {label}\n").as_bytes());
+        }
+    }
+    let _ = file.write(b"    test rbx, rbx\n");
+    let _ = file.write(format!("    jz div_zero_{ip}\n").as_bytes());
+    let _ = file.write(b"    cmp rbx, -1\n");
+    let _ = file.write(format!("    je div_negate_{ip}\n").as_bytes());
+    let _ = file.write(b"    cqo\n");
+    let _ = file.write(b"    idiv rbx\n");
+    let _ = file.write(format!("    jmp div_done_{ip}\n").as_bytes());
+    let _ = file.write(format!("div_zero_{ip}:\n").as_bytes());
+    let _ = file.write(b"    mov rdx, rax\n");
+    let _ = file.write(b"    xor eax, eax\n");
+    let _ = file.write(format!("    jmp div_done_{ip}\n").as_bytes());
+    let _ = file.write(format!("div_negate_{ip}:\n").as_bytes());
+    let _ = file.write(b"    neg rax\n");
+    let _ = file.write(b"    xor edx, edx\n");
+    let _ = file.write(format!("div_done_{ip}:\n").as_bytes());
+}
+
+impl Backend for X86_64 {
+    fn emit_prologue(&self, file: &mut dyn Write) {
+        let _ = file.write(b".intel_syntax noprefix\n");
+        let _ = file.write(b".global _start\n\n");
+        let _ = file.write(b".text\n");
+        let _ = file.write(b"print:\n");
+        let _ = file.write(b"    lea rsi, [rip + num]\n");
+        let _ = file.write(b"    mov rax, rdi\n");
+        let _ = file.write(b"    mov rcx, 10\n");
+        let _ = file.write(b"    mov r8, 19\n");
+        // Convert the magnitude, the sign is written in front of it afterwards.
+        let _ = file.write(b"    test rax, rax\n");
+        let _ = file.write(b"    jns convert_loop\n");
+        let _ = file.write(b"    neg rax\n");
+        let _ = file.write(b"convert_loop:\n");
+        let _ = file.write(b"    xor edx, edx\n");
+        let _ = file.write(b"    div rcx\n");
+        let _ = file.write(b"    add dl, '0'\n");
+        let _ = file.write(b"    mov byte ptr [rsi + r8], dl\n");
+        let _ = file.write(b"    dec r8\n");
+        let _ = file.write(b"    test rax, rax\n");
+        let _ = file.write(b"    jnz convert_loop\n");
+        let _ = file.write(b"    test rdi, rdi\n");
+        let _ = file.write(b"    jns print_digits\n");
+        let _ = file.write(b"    mov byte ptr [rsi + r8], '-'\n");
+        let _ = file.write(b"print_digits:\n");
+        let _ = file.write(b"    mov rax, 1\n");
+        let _ = file.write(b"    mov rdi, 1\n");
+        let _ = file.write(b"    mov rdx, 20\n");
+        let _ = file.write(b"    syscall\n");
+        let _ = file.write(b"    mov rax, 1\n");
+        let _ = file.write(b"    mov rdi, 1\n");
+        let _ = file.write(b"    lea rsi, [rip + newline]\n");
+        let _ = file.write(b"    mov rdx, 1\n");
+        let _ = file.write(b"    syscall\n");
+        let _ = file.write(b"    lea rdi, [rip + num]\n");
+        let _ = file.write(b"    mov rcx, 20\n");
+        let _ = file.write(b"clear_loop:\n");
+        let _ = file.write(b"    mov byte ptr [rdi], 0\n");
+        let _ = file.write(b"    inc rdi\n");
+        let _ = file.write(b"    dec rcx\n");
+        let _ = file.write(b"    jnz clear_loop\n");
+        let _ = file.write(b"    ret\n\n");
+        let _ = file.write(b"_start:\n");
+        let _ = file.write(b"    # r15 is the return stack pointer\n");
+        let _ = file.write(b"    lea r15, [rip + ret_stack_end]\n\n");
+    }
+
+    fn emit_op(&self, file: &mut dyn Write, op: &Op, ip: usize, data: &Data) {
+        use OpValue::{BoolVal, IntVal, MemoryRegion, Proc, StringVal};
+        match op.kind {
+            OpKind::Push => {
+                if let Some(IntVal(val)) = &op.value {
+                    let _ = file.write(b"    # push \n");
+                    let _ = file.write(format!("    movabs rax, {val}\n").as_bytes());
+                    let _ = file.write(b"    push rax\n");
+                } else if let Some(BoolVal(val)) = &op.value {
+                    let _ = file.write(b"    # push \n");
+                    let _ = file.write(format!("    push {}\n", u8::from(*val)).as_bytes());
+                } else if let Some(StringVal(val)) = &op.value {
+                    let val_idx = data
+                        .strings
+                        .iter()
+                        .position(|op| op.value == Some(OpValue::StringVal(val.clone())));
+                    if let Some(idx) = val_idx {
+                        let _ = file.write(b"    # push \n");
+                        let _ = file.write(format!("    push {}\n", val.len()).as_bytes());
+                        let _ =
+                            file.write(format!("    lea rax, [rip + string{idx}]\n").as_bytes());
+                        let _ = file.write(b"    push rax\n");
+                    }
+                }
+            }
+            OpKind::Plus => {
+                let _ = file.write(b"    # plus \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    add rax, rbx\n");
+                emit_overflow_check(file, ip, data);
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Minus => {
+                let _ = file.write(b"    # minus \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    sub rbx, rax\n");
+                emit_overflow_check(file, ip, data);
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Mult => {
+                let _ = file.write(b"    # mult \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    imul rax, rbx\n");
+                emit_overflow_check(file, ip, data);
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Div => {
+                let _ = file.write(b"    # div \n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    pop rax\n");
+                emit_division(file, op, ip, data);
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Mod => {
+                let _ = file.write(b"    # mod \n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    pop rax\n");
+                emit_division(file, op, ip, data);
+                let _ = file.write(b"    push rdx\n");
+            }
+            OpKind::DivMod => {
+                let _ = file.write(b"    # divmod \n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    pop rax\n");
+                emit_division(file, op, ip, data);
+                let _ = file.write(b"    push rax\n");
+                let _ = file.write(b"    push rdx\n");
+            }
+            OpKind::Shl => {
+                let _ = file.write(b"    # shl \n");
+                let _ = file.write(b"    pop rcx\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    shl rbx, cl\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Shr => {
+                let _ = file.write(b"    # shr \n");
+                let _ = file.write(b"    pop rcx\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    shr rbx, cl\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Bor => {
+                let _ = file.write(b"    # bor \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    or rbx, rax\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Band => {
+                let _ = file.write(b"    # band \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    and rbx, rax\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Bxor => {
+                let _ = file.write(b"    # bxor \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor rbx, rax\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Bnot => {
+                let _ = file.write(b"    # bnot \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    not rax\n");
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Equals => {
+                let _ = file.write(b"    # equals \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor ecx, ecx\n");
+                let _ = file.write(b"    cmp rbx, rax\n");
+                let _ = file.write(b"    sete cl\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::NotEquals => {
+                let _ = file.write(b"    # not equals \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor ecx, ecx\n");
+                let _ = file.write(b"    cmp rbx, rax\n");
+                let _ = file.write(b"    setne cl\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::Dup => {
+                let _ = file.write(b"    # dup \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    push rax\n");
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Swap => {
+                let _ = file.write(b"    # swap \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    push rax\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Rot => {
+                let _ = file.write(b"    # rot \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    pop rcx\n");
+                let _ = file.write(b"    push rbx\n");
+                let _ = file.write(b"    push rax\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::Drop => {
+                let _ = file.write(b"    # drop \n");
+                let _ = file.write(b"    pop rax\n");
+            }
+            OpKind::Print => {
+                let _ = file.write(b"    # print \n");
+                let _ = file.write(b"    pop rdi\n");
+                let _ = file.write(b"    call print\n");
+            }
+            OpKind::Write => {
+                let _ = file.write(b"    # write \n");
+                let _ = file.write(b"    pop rdi\n");
+                let _ = file.write(b"    pop rsi\n");
+                let _ = file.write(b"    pop rdx\n");
+                let _ = file.write(b"    mov rax, 1\n");
+                let _ = file.write(b"    syscall\n");
+            }
+            OpKind::Syscall0
+            | OpKind::Syscall1
+            | OpKind::Syscall2
+            | OpKind::Syscall3
+            | OpKind::Syscall4
+            | OpKind::Syscall5
+            | OpKind::Syscall6 => {
+                let args = op.kind.syscall_args().unwrap_or_default();
+                let _ = file.write(format!("    # syscall{args} \n").as_bytes());
+                let _ = file.write(b"    pop rax\n");
+                for reg in &SYSCALL_ARGS[..args] {
+                    let _ = file.write(format!("    pop {reg}\n").as_bytes());
+                }
+                let _ = file.write(b"    syscall\n");
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Over => {
+                let _ = file.write(b"    # over \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    push rbx\n");
+                let _ = file.write(b"    push rax\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::If => {
+                let _ = file.write(b"    # if \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    test rax, rax\n");
+                if let Some(IntVal(ind)) = op.value {
+                    let _ = file.write(format!("    jz addr_{ind}\n").as_bytes());
+                }
+            }
+            OpKind::Else => {
+                let _ = file.write(b"    # else \n");
+                if let Some(IntVal(ind)) = op.value {
+                    let _ = file.write(format!("    jmp addr_{ind}\n").as_bytes());
+                }
+            }
+            OpKind::Exit => {
+                let _ = file.write(b"    # exit \n");
+                let _ = file.write(b"    pop rdi\n");
+                let _ = file.write(b"    mov rax, 60\n");
+                let _ = file.write(b"    syscall\n");
+            }
+            OpKind::Mem => {
+                let _ = file.write(b"    # mem \n");
+                let _ = file.write(b"    lea rax, [rip + mem]\n");
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Memory => {
+                if let Some(MemoryRegion { name, .. }) = &op.value {
+                    if let Some(idx) = data.memory_index(name) {
+                        let _ = file.write(format!("    # memory {name} \n").as_bytes());
+                        let _ =
+                            file.write(format!("    lea rax, [rip + memory{idx}]\n").as_bytes());
+                        let _ = file.write(b"    push rax\n");
+                    }
+                }
+            }
+            OpKind::Load8 => {
+                let _ = file.write(b"    # @8 \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    movzx ebx, byte ptr [rax]\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Store8 => {
+                let _ = file.write(b"    # !8 \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    mov byte ptr [rax], bl\n");
+            }
+            OpKind::Load64 => {
+                let _ = file.write(b"    # @64 \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    mov rbx, qword ptr [rax]\n");
+                let _ = file.write(b"    push rbx\n");
+            }
+            OpKind::Store64 => {
+                let _ = file.write(b"    # !64 \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    mov qword ptr [rax], rbx\n");
+            }
+            OpKind::Fn => {
+                if let Some(Proc { end, .. }) = &op.value {
+                    let _ = file.write(format!("    jmp addr_{}\n", end + 1).as_bytes());
+                }
+                let _ = file.write(format!("proc_{ip}:\n").as_bytes());
+                let _ = file.write(b"    lea rbx, [rip + ret_stack]\n");
+                let _ = file.write(b"    cmp r15, rbx\n");
+                let label = ret_stack_overflow_label(ip);
+                let _ = file.write(format!("    jbe {label}\n").as_bytes());
+                // Move the return address pushed by `call` off the data stack.
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    sub r15, 8\n");
+                let _ = file.write(b"    mov [r15], rax\n");
+            }
+            OpKind::Call => {
+                if let Some(IntVal(fn_ip)) = op.value {
+                    let _ = file.write(b"    # call \n");
+                    let _ = file.write(format!("    call proc_{fn_ip}\n").as_bytes());
+                }
+            }
+            OpKind::Ret => {
+                let _ = file.write(b"    # ret \n");
+                let _ = file.write(b"    mov rax, [r15]\n");
+                let _ = file.write(b"    add r15, 8\n");
+                let _ = file.write(b"    push rax\n");
+                let _ = file.write(b"    ret\n");
+            }
+            OpKind::While => {
+                let _ = file.write(b"    # while \n");
+            }
+            OpKind::Do => {
+                let _ = file.write(b"    # do \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    test rax, rax\n");
+                if let Some(IntVal(ind)) = op.value {
+                    let _ = file.write(format!("    jz addr_{ind}\n").as_bytes());
+                }
+            }
+            OpKind::End => {
+                if let Some(IntVal(ind)) = op.value {
+                    if let Ok(ind) = TryInto::<usize>::try_into(ind) {
+                        if ind != ip {
+                            let _ = file.write(format!("    jmp addr_{ind}\n").as_bytes());
+                        }
+                    }
+                }
+            }
+            OpKind::GT => {
+                let _ = file.write(b"    # > \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor ecx, ecx\n");
+                let _ = file.write(b"    cmp rbx, rax\n");
+                let _ = file.write(b"    setg cl\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::LT => {
+                let _ = file.write(b"    # < \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor ecx, ecx\n");
+                let _ = file.write(b"    cmp rbx, rax\n");
+                let _ = file.write(b"    setl cl\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::GE => {
+                let _ = file.write(b"    # >= \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor ecx, ecx\n");
+                let _ = file.write(b"    cmp rbx, rax\n");
+                let _ = file.write(b"    setge cl\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::LE => {
+                let _ = file.write(b"    # <= \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    xor ecx, ecx\n");
+                let _ = file.write(b"    cmp rbx, rax\n");
+                let _ = file.write(b"    setle cl\n");
+                let _ = file.write(b"    push rcx\n");
+            }
+            OpKind::And => {
+                let _ = file.write(b"    # and \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    and rax, rbx\n");
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Or => {
+                let _ = file.write(b"    # or \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    pop rbx\n");
+                let _ = file.write(b"    or rax, rbx\n");
+                let _ = file.write(b"    push rax\n");
+            }
+            OpKind::Not => {
+                let _ = file.write(b"    # not \n");
+                let _ = file.write(b"    pop rax\n");
+                let _ = file.write(b"    xor rax, 1\n");
+                let _ = file.write(b"    push rax\n");
+            }
+        }
+    }
+
+    fn emit_epilogue(&self, file: &mut dyn Write, data: &Data) {
+        let _ = file.write(b"    # exit syscall\n");
+        let _ = file.write(b"    mov rax, 60\n");
+        let _ = file.write(b"    xor edi, edi\n");
+        let _ = file.write(b"    syscall\n\n");
+        if !data.checks.is_empty() {
+            let _ = file.write(b"# writes the message at rsi of length rdx and exits with 1\n");
+            let _ = file.write(b"runtime_error:\n");
+            let _ = file.write(b"    mov rax, 1\n");
+            let _ = file.write(b"    mov rdi, 2\n");
+            let _ = file.write(b"    syscall\n");
+            let _ = file.write(b"    mov rax, 60\n");
+            let _ = file.write(b"    mov rdi, 1\n");
+            let _ = file.write(b"    syscall\n");
+            for (label, message) in &data.checks {
+                let _ = file.write(format!("{label}:\n").as_bytes());
+                let _ = file.write(format!("    lea rsi, [rip + {label}_message]\n").as_bytes());
+                let _ = file.write(format!("    mov rdx, {}\n", message.len()).as_bytes());
+                let _ = file.write(b"    jmp runtime_error\n");
+            }
+            let _ = file.write(b"\n");
+        }
+        let _ = file.write(b".data\n");
+        let _ = file.write(b"    num: .zero 20\n");
+        let _ = file.write(b"    newline: .asciz \"\\n\" \n");
+        for (idx, string) in data.strings.iter().enumerate() {
+            if let Some(OpValue::StringVal(val)) = &string.value {
+                let _ = file.write(format!("    string{idx}:\n").as_bytes());
+                let _ = file.write(byte_directives(val).as_bytes());
+                let _ = file.write(b"        .byte 0\n");
+            }
+        }
+        for (label, message) in &data.checks {
+            let _ = file.write(format!("    {label}_message:\n").as_bytes());
+            let _ = file.write(byte_directives(message.as_bytes()).as_bytes());
+        }
+        let _ = file.write(b".bss\n");
+        let _ = file.write(b"    .align 16\n");
+        let _ = file.write(format!("    ret_stack: .zero {}\n", RET_STACK_DEPTH * 8).as_bytes());
+        let _ = file.write(b"    ret_stack_end:\n");
+        let _ = file.write(format!("    .balign {MEMORY_ALIGNMENT}\n").as_bytes());
+        let _ = file.write(format!("    mem: .zero {MEM_CAPACITY}\n").as_bytes());
+        for (idx, (name, size)) in data.memories.iter().enumerate() {
+            let _ = file.write(format!("    .balign {MEMORY_ALIGNMENT}\n").as_bytes());
+            let _ = file.write(format!("    memory{idx}: .zero {size} # {name}\n").as_bytes());
+        }
+    }
+
+    fn assemble(&self, filename_pre: &str, silence_flag: bool) -> bool {
+        run_command(
+            Command::new("as")
+                .arg("--64")
+                .arg("-o")
+                .arg(format!("{filename_pre}.o"))
+                .arg(format!("{filename_pre}.s")),
+            silence_flag,
+        )
+    }
+
+    fn link(&self, filename_pre: &str, silence_flag: bool) -> bool {
+        run_command(
+            Command::new("ld")
+                .arg("-o")
+                .arg(filename_pre)
+                .arg(format!("{filename_pre}.o"))
+                .arg("-e")
+                .arg("_start"),
+            silence_flag,
+        )
+    }
+}
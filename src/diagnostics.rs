@@ -0,0 +1,71 @@
+use std::{collections::HashMap, fmt, process::exit};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loc {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// Collects the errors of a compilation phase. Every error or warning is
+/// reported as soon as it is found, `exit_on_errors` then stops once the phase
+/// is done if there were any errors.
+#[derive(Default)]
+pub struct Diagnostics {
+    sources: HashMap<String, String>,
+    errors: usize,
+}
+
+impl Diagnostics {
+    pub fn add_source(&mut self, file: &str, source: &str) {
+        self.sources.insert(file.to_string(), source.to_string());
+    }
+
+    pub fn error(&mut self, loc: &Loc, message: impl fmt::Display) {
+        self.errors += 1;
+        self.report(loc, "error", message);
+    }
+
+    pub fn warning(&mut self, loc: &Loc, message: impl fmt::Display) {
+        self.report(loc, "warning", message);
+    }
+
+    fn report(&self, loc: &Loc, severity: &str, message: impl fmt::Display) {
+        eprintln!("{loc}: {severity}: {message}");
+        let line = self
+            .sources
+            .get(&loc.file)
+            .and_then(|source| source.lines().nth(loc.line.saturating_sub(1)));
+        if let Some(line) = line {
+            let line = line.trim_end_matches('\r');
+            let gutter = loc.line.to_string().len();
+            // Keep tabs so the caret lines up with what the terminal shows.
+            let padding: String = line
+                .chars()
+                .take(loc.col.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            eprintln!("{} |", " ".repeat(gutter));
+            eprintln!("{} | {line}", loc.line);
+            eprintln!("{} | {padding}^", " ".repeat(gutter));
+        }
+    }
+
+    pub const fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    pub fn exit_on_errors(&self) {
+        if self.has_errors() {
+            let plural = if self.errors == 1 { "" } else { "s" };
+            eprintln!("aborting due to {} error{plural}", self.errors);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnostics::{Diagnostics, Loc},
+    lexer::{lex, Token, TokenKind},
+};
+
+/// Splices the tokens of every `include "<path>"` into the token stream of
+/// the including file. Every file is only ever included once.
+struct Includer<'a> {
+    include_paths: &'a [PathBuf],
+    /// Files currently being expanded, outermost first.
+    chain: Vec<PathBuf>,
+    included: HashSet<PathBuf>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl Includer<'_> {
+    fn resolve(&self, including_file: &str, path: &str) -> Option<PathBuf> {
+        let dir = Path::new(including_file)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        std::iter::once(dir)
+            .chain(self.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn expand(&mut self, file: &Path, source: &str) -> Vec<Token> {
+        let canonical = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        self.included.insert(canonical.clone());
+        self.chain.push(canonical);
+
+        let mut result = vec![];
+        let mut tokens = lex(source, &file.to_string_lossy(), self.diagnostics).into_iter();
+        while let Some(token) = tokens.next() {
+            if token.kind != TokenKind::Word || token.text != "include" {
+                result.push(token);
+                continue;
+            }
+            match tokens.next() {
+                Some(path) if path.kind == TokenKind::Str => {
+                    let name = String::from_utf8_lossy(&path.bytes);
+                    result.extend(self.include(&path.file, &name, &token.loc()));
+                }
+                _ => self
+                    .diagnostics
+                    .error(&token.loc(), "expected a file path string after `include`"),
+            }
+        }
+
+        self.chain.pop();
+        result
+    }
+
+    fn include(&mut self, including_file: &str, path: &str, loc: &Loc) -> Vec<Token> {
+        let Some(file) = self.resolve(including_file, path) else {
+            self.diagnostics
+                .error(loc, format!("cannot find included file `{path}`"));
+            return vec![];
+        };
+        let canonical = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+        if let Some(start) = self.chain.iter().position(|f| *f == canonical) {
+            let cycle: Vec<String> = self.chain[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|f| f.display().to_string())
+                .collect();
+            self.diagnostics
+                .error(loc, format!("include cycle: {}", cycle.join(" -> ")));
+            return vec![];
+        }
+        if self.included.contains(&canonical) {
+            return vec![];
+        }
+        match fs::read_to_string(&file) {
+            Ok(source) => self.expand(&file, &source),
+            Err(err) => {
+                self.diagnostics
+                    .error(loc, format!("cannot read included file `{path}`: {err}"));
+                vec![]
+            }
+        }
+    }
+}
+
+/// Lexes `filename` and everything it includes, looking up includes relative
+/// to the including file first and in `include_paths` after that.
+pub fn lex_file(
+    filename: &str,
+    include_paths: &[PathBuf],
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<Token>, ()> {
+    let source = fs::read_to_string(filename).map_err(|_| ())?;
+    let mut includer = Includer {
+        include_paths,
+        chain: vec![],
+        included: HashSet::new(),
+        diagnostics,
+    };
+    Ok(includer.expand(Path::new(filename), &source))
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use crate::diagnostics::{Diagnostics, Loc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Word,
+    Int,
+    Str,
+}
+
+/// A single lexeme of a source file. For string literals `text` holds what
+/// is between the quotes as written and `bytes` the bytes it stands for
+/// after decoding the escape sequences. Character literals are lexed as
+/// `Int` tokens holding their code point.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub bytes: Vec<u8>,
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Token {
+    pub fn loc(&self) -> Loc {
+        Loc {
+            file: self.file.clone(),
+            line: self.line,
+            col: self.col,
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    file: &'a str,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_comment(&self) -> bool {
+        self.chars.get(self.pos) == Some(&'/') && self.chars.get(self.pos + 1) == Some(&'/')
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.advance();
+            } else if self.peek_comment() {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn error(&mut self, line: usize, col: usize, message: impl fmt::Display) {
+        let loc = Loc {
+            file: self.file.to_string(),
+            line,
+            col,
+        };
+        self.diagnostics.error(&loc, message);
+    }
+
+    /// Decodes the escape sequence after a backslash, every escape stands for
+    /// exactly one byte.
+    fn lex_escape(&mut self, line: usize, col: usize) -> Option<u8> {
+        let byte = match self.peek() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => b'\0',
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                self.advance();
+                let digits: String = self.chars[self.pos..].iter().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    self.error(line, col, "`\\x` has to be followed by two hex digits");
+                    return None;
+                }
+                self.advance();
+                self.advance();
+                return u8::from_str_radix(&digits, 16).ok();
+            }
+            Some(c) if c != '\n' => {
+                self.advance();
+                self.error(line, col, format!("unknown escape sequence `\\{c}`"));
+                return None;
+            }
+            _ => {
+                self.error(line, col, "unterminated escape sequence");
+                return None;
+            }
+        };
+        self.advance();
+        Some(byte)
+    }
+
+    fn lex_char(&mut self, line: usize, col: usize) -> Option<i64> {
+        // Skip the opening quote.
+        self.advance();
+        let (escape_line, escape_col) = (self.line, self.col);
+        let value = match self.advance() {
+            Some('\\') => self.lex_escape(escape_line, escape_col).map(i64::from),
+            Some(c) if c != '\'' && c != '\n' => Some(i64::from(u32::from(c))),
+            _ => {
+                self.error(
+                    line,
+                    col,
+                    "character literal has to contain exactly one character",
+                );
+                None
+            }
+        };
+        if self.peek() == Some('\'') {
+            self.advance();
+            return value;
+        }
+        // Skip the rest of the broken literal.
+        let rest = self.lex_word();
+        if value.is_some() {
+            let message = if rest.ends_with('\'') {
+                "character literal has to contain exactly one character"
+            } else {
+                "unterminated character literal"
+            };
+            self.error(line, col, message);
+        }
+        None
+    }
+
+    fn lex_string(&mut self, line: usize, col: usize) -> (String, Vec<u8>) {
+        // Skip the opening quote.
+        self.advance();
+        let start = self.pos;
+        let mut bytes = vec![];
+        loop {
+            let (escape_line, escape_col) = (self.line, self.col);
+            match self.advance() {
+                Some('"') => break,
+                Some('\\') if self.peek().is_some_and(|c| c != '\n') => {
+                    bytes.extend(self.lex_escape(escape_line, escape_col));
+                }
+                Some(c) if c != '\n' && c != '\\' => {
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => {
+                    self.error(line, col, "unterminated string literal");
+                    return (self.chars[start..self.pos].iter().collect(), bytes);
+                }
+            }
+        }
+        (self.chars[start..self.pos - 1].iter().collect(), bytes)
+    }
+
+    fn lex_word(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || self.peek_comment() {
+                break;
+            }
+            text.push(c);
+            self.advance();
+        }
+        text
+    }
+}
+
+pub fn lex(source: &str, file: &str, diagnostics: &mut Diagnostics) -> Vec<Token> {
+    diagnostics.add_source(file, source);
+    let mut lexer = Lexer {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+        file,
+        diagnostics,
+    };
+    let mut tokens = vec![];
+    loop {
+        lexer.skip_whitespace_and_comments();
+        let Some(c) = lexer.peek() else {
+            break;
+        };
+        let (line, col) = (lexer.line, lexer.col);
+        let (kind, text, bytes) = if c == '"' {
+            let (text, bytes) = lexer.lex_string(line, col);
+            (TokenKind::Str, text, bytes)
+        } else if c == '\'' {
+            match lexer.lex_char(line, col) {
+                Some(value) => (TokenKind::Int, value.to_string(), vec![]),
+                None => continue,
+            }
+        } else {
+            let text = lexer.lex_word();
+            let digits = text.strip_prefix('-').unwrap_or(&text);
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                (TokenKind::Int, text, vec![])
+            } else {
+                (TokenKind::Word, text, vec![])
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text,
+            bytes,
+            file: file.to_string(),
+            line,
+            col,
+        });
+    }
+    tokens
+}
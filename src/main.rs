@@ -1,7 +1,11 @@
 use std::{
+    collections::HashMap,
     env,
-    fs::{self, File},
-    io::{LineWriter, Write},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    iter::Peekable,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
     process::exit,
 };
 
@@ -11,10 +15,23 @@ use strum_macros::EnumCount;
 #[macro_use]
 extern crate static_assertions;
 
+mod backend;
+mod diagnostics;
+mod include;
+mod lexer;
+mod typecheck;
+
+use backend::{compile_program, Target};
+use diagnostics::{Diagnostics, Loc};
+use include::lex_file;
+use lexer::{Token, TokenKind};
+use typecheck::{type_check_program, DataType, Signature};
+
 #[derive(Debug, EnumCount, PartialEq, Clone, Copy)]
 enum OpKind {
     Push,
     If,
+    Else,
     While,
     Do,
     End,
@@ -22,9 +39,18 @@ enum OpKind {
     Minus,
     Mult,
     Div,
+    Mod,
+    DivMod,
+    Shl,
+    Shr,
+    Bor,
+    Band,
+    Bxor,
+    Bnot,
     Print,
     Write,
     Equals,
+    NotEquals,
     Dup,
     Swap,
     Rot,
@@ -32,316 +58,921 @@ enum OpKind {
     Over,
     GT,
     LT,
+    GE,
+    LE,
+    And,
+    Or,
+    Not,
+    Fn,
+    Call,
+    Ret,
+    Mem,
+    Load8,
+    Store8,
+    Load64,
+    Store64,
+    Memory,
+    Syscall0,
+    Syscall1,
+    Syscall2,
+    Syscall3,
+    Syscall4,
+    Syscall5,
+    Syscall6,
+    Exit,
+}
+
+impl OpKind {
+    /// The number of arguments a `syscallN` op passes besides the syscall
+    /// number.
+    const fn syscall_args(self) -> Option<usize> {
+        match self {
+            Self::Syscall0 => Some(0),
+            Self::Syscall1 => Some(1),
+            Self::Syscall2 => Some(2),
+            Self::Syscall3 => Some(3),
+            Self::Syscall4 => Some(4),
+            Self::Syscall5 => Some(5),
+            Self::Syscall6 => Some(6),
+            _ => None,
+        }
+    }
 }
 
+/// Size in bytes of the global buffer `mem` points to.
+const MEM_CAPACITY: usize = 640 * 1024;
+/// Largest size in bytes a `memory` region can have.
+const MAX_MEMORY_REGION_SIZE: u64 = 1 << 30;
+/// How deep procedure calls can nest before the program stops with a
+/// "return stack overflow" runtime error.
+const RET_STACK_DEPTH: usize = 16 * 1024;
+/// Alignment in bytes of `mem` and every `memory` region.
+const MEMORY_ALIGNMENT: usize = 16;
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 enum OpValue {
-    IntVal(u64),
-    StringVal(String),
+    IntVal(i64),
+    BoolVal(bool),
+    StringVal(Vec<u8>),
+    /// A named region declared with `memory`.
+    MemoryRegion {
+        name: String,
+        size: u64,
+    },
+    /// A `fn` definition. `end` is the ip of its closing `end`, it's resolved
+    /// by `cross_reference_blocks`.
+    Proc {
+        name: String,
+        signature: Signature,
+        end: usize,
+    },
 }
 
 #[derive(Debug, Clone)]
 struct Op {
     kind: OpKind,
     value: Option<OpValue>,
+    loc: Loc,
+}
+
+/// The words all builtin ops are written as.
+const BUILTIN_WORDS: &[(&str, OpKind)] = &[
+    ("+", OpKind::Plus),
+    ("-", OpKind::Minus),
+    ("*", OpKind::Mult),
+    ("/", OpKind::Div),
+    ("mod", OpKind::Mod),
+    ("divmod", OpKind::DivMod),
+    ("shl", OpKind::Shl),
+    ("shr", OpKind::Shr),
+    ("bor", OpKind::Bor),
+    ("band", OpKind::Band),
+    ("bxor", OpKind::Bxor),
+    ("bnot", OpKind::Bnot),
+    ("print", OpKind::Print),
+    ("write", OpKind::Write),
+    ("=", OpKind::Equals),
+    ("!=", OpKind::NotEquals),
+    ("dup", OpKind::Dup),
+    ("swap", OpKind::Swap),
+    ("rot", OpKind::Rot),
+    ("drop", OpKind::Drop),
+    ("over", OpKind::Over),
+    ("if", OpKind::If),
+    ("else", OpKind::Else),
+    ("while", OpKind::While),
+    ("do", OpKind::Do),
+    ("end", OpKind::End),
+    ("fn", OpKind::Fn),
+    ("mem", OpKind::Mem),
+    ("@8", OpKind::Load8),
+    ("!8", OpKind::Store8),
+    ("@64", OpKind::Load64),
+    ("!64", OpKind::Store64),
+    (">", OpKind::GT),
+    ("<", OpKind::LT),
+    (">=", OpKind::GE),
+    ("<=", OpKind::LE),
+    ("and", OpKind::And),
+    ("or", OpKind::Or),
+    ("not", OpKind::Not),
+    ("syscall0", OpKind::Syscall0),
+    ("syscall1", OpKind::Syscall1),
+    ("syscall2", OpKind::Syscall2),
+    ("syscall3", OpKind::Syscall3),
+    ("syscall4", OpKind::Syscall4),
+    ("syscall5", OpKind::Syscall5),
+    ("syscall6", OpKind::Syscall6),
+    ("exit", OpKind::Exit),
+];
+
+fn builtin_word_as_op_kind(word: &str) -> Option<OpKind> {
+    BUILTIN_WORDS
+        .iter()
+        .find(|(builtin, _)| *builtin == word)
+        .map(|(_, kind)| *kind)
+}
+
+/// The word an op of `kind` is written as, if it's a builtin.
+fn op_kind_word(kind: OpKind) -> Option<&'static str> {
+    BUILTIN_WORDS
+        .iter()
+        .find(|(_, builtin)| *builtin == kind)
+        .map(|(word, _)| *word)
+}
+
+/// Words that can't be used as the name of a definition.
+fn is_reserved_word(word: &str) -> bool {
+    builtin_word_as_op_kind(word).is_some()
+        || DataType::from_word(word).is_some()
+        || matches!(
+            word,
+            "const" | "memory" | "include" | "--" | "true" | "false"
+        )
+}
+
+/// What a user defined name stands for.
+enum Definition {
+    Const(i64),
+    /// The ip of the `fn` op.
+    Proc(usize),
+    /// The size of the region in bytes.
+    Memory(u64),
 }
 
-fn parse_file(filename: String) -> Result<Vec<String>, ()> {
-    let contents = fs::read_to_string(filename);
-    if let Ok(contents) = contents {
-        let lines = contents
-            .split('\n')
-            .map(std::string::ToString::to_string)
-            .collect();
-        return Ok(lines);
+/// Takes the name of a new definition from `tokens` and checks that it's free.
+fn parse_definition_name(
+    tokens: &mut impl Iterator<Item = Token>,
+    keyword: &Token,
+    definitions: &HashMap<String, Definition>,
+    diagnostics: &mut Diagnostics,
+) -> Option<Token> {
+    let Some(name) = tokens.next() else {
+        let message = format!("expected a name after `{}`", keyword.text);
+        diagnostics.error(&keyword.loc(), message);
+        return None;
+    };
+    if name.kind != TokenKind::Word || is_reserved_word(&name.text) {
+        diagnostics.error(&name.loc(), format!("`{}` is not a valid name", name.text));
+        return None;
+    }
+    if definitions.contains_key(&name.text) {
+        diagnostics.error(&name.loc(), format!("redefinition of `{}`", name.text));
+        return None;
+    }
+    Some(name)
+}
+
+/// Parses the optional `<inputs> -- <outputs>` type lists after the name of a
+/// `fn`. Without them the procedure neither takes nor leaves any values.
+fn parse_signature(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Signature {
+    let parse_types = |tokens: &mut Peekable<_>| {
+        let mut types = vec![];
+        while let Some(data_type) = tokens
+            .peek()
+            .filter(|token: &&Token| token.kind == TokenKind::Word)
+            .and_then(|token| DataType::from_word(&token.text))
+        {
+            tokens.next();
+            types.push(data_type);
+        }
+        types
+    };
+    let inputs = parse_types(tokens);
+    let outputs = if tokens
+        .next_if(|token| token.kind == TokenKind::Word && token.text == "--")
+        .is_some()
+    {
+        parse_types(tokens)
+    } else {
+        vec![]
+    };
+    Signature { inputs, outputs }
+}
+
+/// The value of an `Int` token, reports literals that don't fit in 64 bits.
+fn parse_int(token: &Token, diagnostics: &mut Diagnostics) -> Option<i64> {
+    let value = token.text.parse::<i64>().ok();
+    if value.is_none() {
+        let message = format!(
+            "integer literal `{}` doesn't fit in a signed 64 bit integer",
+            token.text
+        );
+        diagnostics.error(&token.loc(), message);
+    }
+    value
+}
+
+/// The size of a `memory` region declared as `value` bytes.
+fn memory_region_size(value: i64) -> Result<u64, String> {
+    match u64::try_from(value) {
+        Ok(size @ 1..=MAX_MEMORY_REGION_SIZE) => Ok(size),
+        Ok(1..) => Err(format!(
+            "memory regions can be at most {MAX_MEMORY_REGION_SIZE} bytes"
+        )),
+        _ => Err("memory regions need a positive size".to_string()),
+    }
+}
+
+/// Evaluates the body of a `const` or `memory` definition up to and including
+/// its `end`.
+fn evaluate_const_expr(
+    tokens: &mut impl Iterator<Item = Token>,
+    keyword: &Token,
+    definitions: &HashMap<String, Definition>,
+    diagnostics: &mut Diagnostics,
+) -> Option<i64> {
+    let start = &keyword.loc();
+    let mut stack: Vec<i64> = vec![];
+    let mut valid = true;
+    loop {
+        let Some(token) = tokens.next() else {
+            let message = format!("unclosed `{}` definition, missing `end`", keyword.text);
+            diagnostics.error(start, message);
+            return None;
+        };
+        let loc = token.loc();
+        let value = match (token.kind, definitions.get(&token.text)) {
+            (TokenKind::Int, _) => {
+                let value = parse_int(&token, diagnostics);
+                valid &= value.is_some();
+                value
+            }
+            (TokenKind::Word, _) if token.text == "end" => break,
+            (TokenKind::Word, Some(Definition::Const(value))) => Some(*value),
+            (TokenKind::Word, _) => {
+                // Only the ops `evaluate_arithmetic` knows are constant.
+                let kind = builtin_word_as_op_kind(&token.text)
+                    .filter(|kind| evaluate_arithmetic(*kind, 1, 1).is_some());
+                if let Some(kind) = kind {
+                    if let (Some(rhs), Some(lhs)) = (stack.pop(), stack.pop()) {
+                        if matches!(kind, OpKind::Div | OpKind::Mod) && rhs == 0 {
+                            diagnostics.error(&loc, "division by zero in constant expression");
+                            valid = false;
+                            None
+                        } else {
+                            evaluate_arithmetic(kind, lhs, rhs)
+                        }
+                    } else {
+                        diagnostics.error(
+                            &loc,
+                            format!("not enough values on the stack for `{}`", token.text),
+                        );
+                        valid = false;
+                        None
+                    }
+                } else {
+                    diagnostics.error(
+                        &loc,
+                        format!("`{}` is not allowed in a constant expression", token.text),
+                    );
+                    valid = false;
+                    None
+                }
+            }
+            (TokenKind::Str, _) => {
+                diagnostics.error(&loc, "strings are not allowed in a constant expression");
+                valid = false;
+                None
+            }
+        };
+        stack.extend(value);
     }
 
-    Err(())
+    if !valid {
+        return None;
+    }
+    if stack.len() != 1 {
+        diagnostics.error(
+            start,
+            format!(
+                "constant expression has to produce exactly one value, got {}",
+                stack.len()
+            ),
+        );
+        return None;
+    }
+    stack.pop()
 }
 
-fn parse_word_as_op(lines: Vec<String>) -> Vec<Op> {
+fn parse_word_as_op(tokens: Vec<Token>, target: Target, diagnostics: &mut Diagnostics) -> Vec<Op> {
     let mut result: Vec<Op> = vec![];
-    for mut line in lines {
-        let comment = line.find("//");
-        if let Some(comment_ind) = comment {
-            line = line.chars().take(comment_ind).collect();
-        }
-        let words: Vec<&str> = line.split_inclusive(['\n', '"', ' ']).collect();
-        let mut curr_string: String = String::new();
-        for word in words {
-            // Exhaustive handling of OpKinds in parse_word_as_op
-            const_assert!(OpKind::COUNT == 19);
-            use OpValue::{IntVal, StringVal};
-            let trimmed_word = word.trim();
-            // println!("word |{word}|");
-            // println!("tw |{trimmed_word}|");
-            if trimmed_word.is_empty() && curr_string.is_empty() {
-                continue;
-            }
-            if let Ok(num) = trimmed_word.parse::<u64>() {
-                result.push(Op {
-                    kind: OpKind::Push,
-                    value: Some(IntVal(num)),
-                });
-            } else if curr_string.starts_with('"')
-                && curr_string.ends_with('"')
-                && curr_string.chars().count() > 1
-            {
-                result.push(Op {
-                    kind: OpKind::Push,
-                    value: Some(StringVal(curr_string.clone().replace('"', ""))),
-                });
-                curr_string = String::new();
-            } else if word == "\"" && curr_string.is_empty() {
-                curr_string += word;
-            } else if word == "\"" && !curr_string.is_empty() {
-                result.push(Op {
-                    kind: OpKind::Push,
-                    value: Some(StringVal(curr_string.clone().replace('"', ""))),
-                });
-                curr_string = String::new();
-            } else if !curr_string.is_empty() {
-                curr_string += word;
-            } else if trimmed_word == "+" {
-                result.push(Op {
-                    kind: OpKind::Plus,
-                    value: None,
-                });
-            } else if trimmed_word == "-" {
-                result.push(Op {
-                    kind: OpKind::Minus,
-                    value: None,
-                });
-            } else if trimmed_word == "*" {
-                result.push(Op {
-                    kind: OpKind::Mult,
-                    value: None,
-                });
-            } else if trimmed_word == "/" {
-                result.push(Op {
-                    kind: OpKind::Div,
-                    value: None,
-                });
-            } else if trimmed_word == "print" {
-                result.push(Op {
-                    kind: OpKind::Print,
-                    value: None,
-                });
-            } else if trimmed_word == "write" {
-                result.push(Op {
-                    kind: OpKind::Write,
-                    value: None,
-                });
-            } else if trimmed_word == "=" {
-                result.push(Op {
-                    kind: OpKind::Equals,
-                    value: None,
-                });
-            } else if trimmed_word == "dup" {
-                result.push(Op {
-                    kind: OpKind::Dup,
-                    value: None,
-                });
-            } else if trimmed_word == "swap" {
-                result.push(Op {
-                    kind: OpKind::Swap,
-                    value: None,
-                });
-            } else if trimmed_word == "rot" {
-                result.push(Op {
-                    kind: OpKind::Rot,
-                    value: None,
-                });
-            } else if trimmed_word == "drop" {
-                result.push(Op {
-                    kind: OpKind::Drop,
-                    value: None,
-                });
-            } else if trimmed_word == "over" {
-                result.push(Op {
-                    kind: OpKind::Over,
-                    value: None,
-                });
-            } else if trimmed_word == "if" {
-                result.push(Op {
-                    kind: OpKind::If,
-                    value: None,
-                });
-            } else if trimmed_word == "while" {
-                result.push(Op {
-                    kind: OpKind::While,
-                    value: None,
-                });
-            } else if trimmed_word == "do" {
-                result.push(Op {
-                    kind: OpKind::Do,
-                    value: None,
-                });
-            } else if trimmed_word == "end" {
-                result.push(Op {
-                    kind: OpKind::End,
-                    value: None,
-                });
-            } else if trimmed_word == ">" {
-                result.push(Op {
-                    kind: OpKind::GT,
-                    value: None,
-                });
-            } else if trimmed_word == "<" {
-                result.push(Op {
-                    kind: OpKind::LT,
-                    value: None,
-                });
-            } else {
-                panic!("Unknown word: {word}")
-            }
-            // println!("curr |{curr_string}|");
+    // The syscall numbers and flags of the target are predefined constants.
+    let mut definitions: HashMap<String, Definition> = target
+        .constants()
+        .iter()
+        .map(|(name, value)| (name.to_string(), Definition::Const(*value)))
+        .collect();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        // Exhaustive handling of OpKinds in parse_word_as_op
+        const_assert!(OpKind::COUNT == 51);
+        use OpValue::{BoolVal, IntVal, MemoryRegion, Proc, StringVal};
+        let loc = token.loc();
+        match token.kind {
+            TokenKind::Int => {
+                if let Some(num) = parse_int(&token, diagnostics) {
+                    result.push(Op {
+                        kind: OpKind::Push,
+                        value: Some(IntVal(num)),
+                        loc,
+                    });
+                }
+            }
+            TokenKind::Str => result.push(Op {
+                kind: OpKind::Push,
+                value: Some(StringVal(token.bytes)),
+                loc,
+            }),
+            TokenKind::Word => {
+                let (kind, value) = match definitions.get(&token.text) {
+                    Some(Definition::Const(value)) => (OpKind::Push, Some(IntVal(*value))),
+                    Some(Definition::Proc(fn_ip)) => (OpKind::Call, Some(IntVal(*fn_ip as i64))),
+                    Some(Definition::Memory(size)) => (
+                        OpKind::Memory,
+                        Some(MemoryRegion {
+                            name: token.text.clone(),
+                            size: *size,
+                        }),
+                    ),
+                    None if matches!(token.text.as_str(), "true" | "false") => {
+                        (OpKind::Push, Some(BoolVal(token.text == "true")))
+                    }
+                    None if matches!(token.text.as_str(), "const" | "memory") => {
+                        let name =
+                            parse_definition_name(&mut tokens, &token, &definitions, diagnostics);
+                        let value =
+                            evaluate_const_expr(&mut tokens, &token, &definitions, diagnostics);
+                        if let (Some(name), Some(value)) = (name, value) {
+                            if token.text == "const" {
+                                definitions.insert(name.text, Definition::Const(value));
+                            } else {
+                                match memory_region_size(value) {
+                                    Ok(size) => {
+                                        definitions.insert(name.text, Definition::Memory(size));
+                                    }
+                                    Err(message) => diagnostics.error(&name.loc(), message),
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    None => {
+                        let Some(kind) = builtin_word_as_op_kind(&token.text) else {
+                            diagnostics.error(&loc, format!("unknown word `{}`", token.text));
+                            continue;
+                        };
+                        if kind == OpKind::Fn {
+                            let name = parse_definition_name(
+                                &mut tokens,
+                                &token,
+                                &definitions,
+                                diagnostics,
+                            );
+                            let signature = parse_signature(&mut tokens);
+                            let value = name.map(|name| {
+                                definitions
+                                    .insert(name.text.clone(), Definition::Proc(result.len()));
+                                Proc {
+                                    name: name.text,
+                                    signature,
+                                    end: 0,
+                                }
+                            });
+                            (kind, value)
+                        } else {
+                            (kind, None)
+                        }
+                    }
+                };
+                result.push(Op { kind, value, loc });
+            }
         }
     }
 
     result
 }
 
-fn cross_reference_blocks(program: &mut Vec<Op>, ip_start: usize) {
-    let mut ip = ip_start;
-    let mut curr_if = None;
-    let mut curr_if_ip = 0;
-    let mut curr_while = None;
-    let mut curr_while_ip = 0;
-    let mut curr_do = None;
-    let mut curr_do_ip = 0;
-    while ip < program.len() {
-        let mut op = program[ip].clone();
+/// Resolves the jump targets of all block ops in one pass over the program,
+/// keeping the ips of the currently open blocks on a stack.
+fn cross_reference_blocks(program: &mut [Op], diagnostics: &mut Diagnostics) {
+    use OpValue::IntVal;
+    let mut stack: Vec<usize> = vec![];
+    for ip in 0..program.len() {
         // Exhaustive handling of Ops in cross_reference_blocks.
         // Remember not all need to be accounted for here only Ops that form blocks.
-        const_assert!(OpKind::COUNT == 19);
-        use OpValue::IntVal;
-        if op.kind == OpKind::If {
-            if curr_if.is_none() && op.value.is_none() {
-                curr_if = Some(op.clone());
-                curr_if_ip = ip;
-            } else if curr_if.is_some() && op.value.is_none() {
-                cross_reference_blocks(program, ip);
-            }
-        } else if op.kind == OpKind::While {
-            if curr_while.is_none() && op.value.is_none() {
-                curr_while = Some(op.clone());
-                curr_while_ip = ip;
-            } else if curr_while.is_some() && op.value.is_none() {
-                cross_reference_blocks(program, ip);
+        const_assert!(OpKind::COUNT == 51);
+        match program[ip].kind {
+            OpKind::If | OpKind::While => stack.push(ip),
+            OpKind::Fn => {
+                if !stack.is_empty() {
+                    diagnostics.error(
+                        &program[ip].loc,
+                        "procedures can only be defined at the top level",
+                    );
+                }
+                stack.push(ip);
             }
-        }
-        if op.kind == OpKind::Do {
-            if curr_do.is_none() && op.value.is_none() {
-                curr_do = Some(op);
-                curr_do_ip = ip;
-            } else if curr_do.is_some() && op.value.is_none() {
-                cross_reference_blocks(program, ip);
-            }
-        } else if op.kind == OpKind::End {
-            if let Some(ref mut if_op) = curr_if {
-                if if_op.value.is_none() && op.value.is_none() {
-                    let ip64: Option<u64> = (ip + 1).try_into().ok();
-                    if let Some(ip64) = ip64 {
-                        if_op.value = Some(IntVal(ip64));
+            OpKind::Do => match stack.last() {
+                Some(&while_ip) if program[while_ip].kind == OpKind::While => {
+                    stack.pop();
+                    // Remember the while until the end of the block is known.
+                    program[ip].value = Some(IntVal(while_ip as i64));
+                    stack.push(ip);
+                }
+                _ => diagnostics.error(&program[ip].loc, "`do` without a matching `while`"),
+            },
+            OpKind::Else => match stack.pop() {
+                Some(if_ip) if program[if_ip].kind == OpKind::If => {
+                    program[if_ip].value = Some(IntVal(ip as i64 + 1));
+                    stack.push(ip);
+                }
+                Some(block_ip) => {
+                    diagnostics.error(&program[ip].loc, "`else` without a matching `if`");
+                    stack.push(block_ip);
+                }
+                None => diagnostics.error(&program[ip].loc, "`else` without a matching `if`"),
+            },
+            OpKind::End => match stack.pop() {
+                Some(block_ip) if program[block_ip].kind == OpKind::If => {
+                    program[block_ip].value = Some(IntVal(ip as i64 + 1));
+                    program[ip].value = Some(IntVal(ip as i64));
+                }
+                Some(else_ip) if program[else_ip].kind == OpKind::Else => {
+                    program[else_ip].value = Some(IntVal(ip as i64 + 1));
+                    program[ip].value = Some(IntVal(ip as i64));
+                }
+                Some(fn_ip) if program[fn_ip].kind == OpKind::Fn => {
+                    if let Some(OpValue::Proc { end, .. }) = &mut program[fn_ip].value {
+                        *end = ip;
                     }
-                    let ip64: Option<u64> = ip.try_into().ok();
-                    if let Some(ip64) = ip64 {
-                        op.value = Some(IntVal(ip64));
+                    program[ip].kind = OpKind::Ret;
+                }
+                Some(block_ip) if program[block_ip].kind == OpKind::Do => {
+                    if let Some(IntVal(while_ip)) = program[block_ip].value {
+                        program[while_ip as usize].value = Some(IntVal(0));
+                        program[ip].value = Some(IntVal(while_ip));
                     }
-                    program[curr_if_ip] = if_op.clone();
-                    program[ip] = op.clone();
-                    curr_if = None;
-                    curr_if_ip = 0;
+                    program[block_ip].value = Some(IntVal(ip as i64 + 1));
                 }
+                Some(block_ip) => {
+                    let message = format!(
+                        "`end` closes the `while` at {} before its `do`",
+                        program[block_ip].loc
+                    );
+                    diagnostics.error(&program[ip].loc, message);
+                }
+                None => diagnostics.error(&program[ip].loc, "`end` without an open block"),
+            },
+            _ => {}
+        }
+    }
+
+    for block_ip in stack {
+        let op = &program[block_ip];
+        match (op.kind, &op.value) {
+            (OpKind::If | OpKind::Else, _) => {
+                diagnostics.error(&op.loc, "unclosed `if` block, missing `end`");
             }
+            (OpKind::Fn, _) => diagnostics.error(&op.loc, "unclosed `fn` block, missing `end`"),
+            (OpKind::Do, Some(IntVal(while_ip))) => {
+                let while_loc = &program[*while_ip as usize].loc;
+                diagnostics.error(while_loc, "unclosed `while` block, missing `end`");
+            }
+            _ => diagnostics.error(&op.loc, "unclosed `while` block, missing `do`"),
+        }
+    }
+}
 
-            if let Some(ref mut while_op) = curr_while {
-                if let Some(ref mut do_op) = curr_do {
-                    if while_op.value.is_none() && op.value.is_none() {
-                        while_op.value = Some(IntVal(0));
-                        let curr_while_ip64: Option<u64> = curr_while_ip.try_into().ok();
-                        if let Some(curr_while_ip64) = curr_while_ip64 {
-                            op.value = Some(IntVal(curr_while_ip64));
-                        }
-                        program[ip] = op;
-                        program[curr_while_ip] = while_op.clone();
-                        curr_while = None;
-                        curr_while_ip = 0;
-
-                        if do_op.value.is_none() {
-                            let ip64: Option<u64> = (ip + 1).try_into().ok();
-                            if let Some(ip64) = ip64 {
-                                do_op.value = Some(IntVal(ip64));
-                            }
-                            program[curr_do_ip] = do_op.clone();
-                            curr_do = None;
-                            curr_do_ip = 0;
-                        }
-                    }
+/// Arithmetic shared by the simulator and the compile time evaluator. `lhs` is
+/// the deeper of the two operands. Results wrap around in two's complement like
+/// they do in the native backends, dividing by zero gives 0 with `lhs` as the
+/// remainder. Returns `None` for non arithmetic ops.
+const fn evaluate_arithmetic(kind: OpKind, lhs: i64, rhs: i64) -> Option<i64> {
+    match kind {
+        OpKind::Plus => Some(lhs.wrapping_add(rhs)),
+        OpKind::Minus => Some(lhs.wrapping_sub(rhs)),
+        OpKind::Mult => Some(lhs.wrapping_mul(rhs)),
+        OpKind::Div if rhs == 0 => Some(0),
+        OpKind::Div => Some(lhs.wrapping_div(rhs)),
+        OpKind::Mod if rhs == 0 => Some(lhs),
+        OpKind::Mod => Some(lhs.wrapping_rem(rhs)),
+        // Only the low 6 bits of the shift count are used, like on the hardware.
+        OpKind::Shl => Some(lhs << (rhs & 63)),
+        OpKind::Shr => Some(((lhs as u64) >> (rhs & 63)) as i64),
+        OpKind::Bor => Some(lhs | rhs),
+        OpKind::Band => Some(lhs & rhs),
+        OpKind::Bxor => Some(lhs ^ rhs),
+        _ => None,
+    }
+}
+
+/// What `--checked` mode traps on instead of silently wrapping around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuntimeCheck {
+    DivisionByZero,
+    Overflow,
+}
+
+impl RuntimeCheck {
+    /// The checks ops of `kind` get in `--checked` mode.
+    const fn for_kind(kind: OpKind) -> &'static [Self] {
+        match kind {
+            OpKind::Plus | OpKind::Minus | OpKind::Mult => &[Self::Overflow],
+            OpKind::Div | OpKind::DivMod => &[Self::DivisionByZero, Self::Overflow],
+            OpKind::Mod => &[Self::DivisionByZero],
+            _ => &[],
+        }
+    }
+
+    /// Whether an op of `kind` fails the check for its operands, `lhs` being
+    /// the deeper one.
+    const fn fails(self, kind: OpKind, lhs: i64, rhs: i64) -> bool {
+        match (self, kind) {
+            (Self::DivisionByZero, _) => rhs == 0,
+            (Self::Overflow, OpKind::Plus) => lhs.checked_add(rhs).is_none(),
+            (Self::Overflow, OpKind::Minus) => lhs.checked_sub(rhs).is_none(),
+            (Self::Overflow, OpKind::Mult) => lhs.checked_mul(rhs).is_none(),
+            (Self::Overflow, _) => lhs == i64::MIN && rhs == -1,
+        }
+    }
+
+    const fn description(self) -> &'static str {
+        match self {
+            Self::DivisionByZero => "division by zero",
+            Self::Overflow => "arithmetic overflow",
+        }
+    }
+}
+
+/// The line a runtime error of `op` is reported with, by the simulator and by
+/// compiled programs alike.
+fn runtime_error_message(op: &Op, message: impl std::fmt::Display) -> String {
+    format!("{}: runtime error: {message}", op.loc)
+}
+
+/// Reports an error found while simulating `op` and stops the simulation.
+fn runtime_error(op: &Op, message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", runtime_error_message(op, message));
+    exit(1);
+}
+
+/// Address of the first byte of simulator memory. Nothing is mapped below it,
+/// so null pointers and small offsets from them are caught.
+const SIM_MEMORY_BASE: usize = 0x10000;
+/// Unmapped bytes the simulator leaves after every region, so running off its
+/// end is caught instead of landing in the next one.
+const SIM_GUARD_SIZE: usize = 0x1000;
+
+/// The address space of the simulator. The `mem` buffer starts at
+/// `SIM_MEMORY_BASE`, every `memory` region and string literal follows it in
+/// order of first use, each behind a guard gap.
+struct SimMemory {
+    regions: Vec<SimRegion>,
+    /// Start address of the region of every string literal, by its bytes.
+    strings: HashMap<Vec<u8>, usize>,
+}
+
+struct SimRegion {
+    /// Only shows up in error messages.
+    name: String,
+    start: usize,
+    bytes: Vec<u8>,
+}
+
+impl SimMemory {
+    fn new(program: &[Op]) -> Self {
+        let mut memory = Self {
+            regions: vec![],
+            strings: HashMap::new(),
+        };
+        memory.allocate("mem", vec![0; MEM_CAPACITY]);
+        for op in program {
+            match &op.value {
+                Some(OpValue::MemoryRegion { name, size })
+                    if memory.region_start(name).is_none() =>
+                {
+                    memory.allocate(name, vec![0; *size as usize]);
+                }
+                Some(OpValue::StringVal(string)) if !memory.strings.contains_key(string) => {
+                    // Zero terminated like the strings of the backends.
+                    let name = format!("{:?}", String::from_utf8_lossy(string));
+                    let start = memory.allocate(&name, [string.as_slice(), &[0]].concat());
+                    memory.strings.insert(string.clone(), start);
+                }
+                _ => {}
+            }
+        }
+        memory
+    }
+
+    /// Maps a region holding `bytes` and returns its start address.
+    fn allocate(&mut self, name: &str, bytes: Vec<u8>) -> usize {
+        let start = self
+            .regions
+            .last()
+            .map_or(SIM_MEMORY_BASE, |last| {
+                last.start + last.bytes.len() + SIM_GUARD_SIZE
+            })
+            .next_multiple_of(MEMORY_ALIGNMENT);
+        self.regions.push(SimRegion {
+            name: name.to_string(),
+            start,
+            bytes,
+        });
+        start
+    }
+
+    fn region_start(&self, name: &str) -> Option<usize> {
+        self.regions
+            .iter()
+            .find(|region| region.name == name)
+            .map(|region| region.start)
+    }
+
+    /// The zero terminated string at `addr`, without the zero.
+    fn c_string(&mut self, addr: i64) -> Result<&[u8], String> {
+        let mut len = 0;
+        while self.access(addr + len, 1)?[0] != 0 {
+            len += 1;
+        }
+        self.access(addr, len).map(|bytes| &*bytes)
+    }
+
+    /// The little endian value of `size` bytes at `addr`, zero extended.
+    fn load(&mut self, addr: i64, size: usize) -> Result<i64, String> {
+        let mut value = [0; 8];
+        value[..size].copy_from_slice(self.access(addr, size as i64)?);
+        Ok(i64::from_le_bytes(value))
+    }
+
+    /// Stores the low `size` bytes of `value` at `addr`, little endian.
+    fn store(&mut self, addr: i64, size: usize, value: i64) -> Result<(), String> {
+        self.access(addr, size as i64)?
+            .copy_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    /// The `len` bytes at `addr`, which all have to lie in the same region.
+    /// Accesses that start in the guard gap behind a region count as overruns
+    /// of it.
+    fn access(&mut self, addr: i64, len: i64) -> Result<&mut [u8], String> {
+        let region = self.regions.iter_mut().rev().find(|region| {
+            (region.start as i64..(region.start + region.bytes.len() + SIM_GUARD_SIZE) as i64)
+                .contains(&addr)
+        });
+        let Some(SimRegion { name, start, bytes }) = region else {
+            return Err(format!(
+                "access of {len} bytes at address {addr} is outside of every memory region"
+            ));
+        };
+        let (offset, size) = (addr - *start as i64, bytes.len());
+        if len < 0 || offset.checked_add(len).is_none_or(|end| end > size as i64) {
+            return Err(format!(
+                "access of {len} bytes at offset {offset} overruns `{name}` of {size} bytes"
+            ));
+        }
+        Ok(&mut bytes[offset as usize..(offset + len) as usize])
+    }
+}
+
+/// `EBADF` and `EINVAL` have the same numbers on every target.
+const EBADF: i32 = 9;
+const EINVAL: i32 = 22;
+
+/// The host side of the syscalls the simulator emulates. Like on Linux a
+/// failed syscall returns the negated errno.
+struct SimOs {
+    target: Target,
+    /// The files the program opened, by fd.
+    files: HashMap<i64, File>,
+}
+
+impl SimOs {
+    fn new(target: Target) -> Self {
+        Self {
+            target,
+            files: HashMap::new(),
+        }
+    }
+
+    fn file(&mut self, fd: i64) -> io::Result<&mut File> {
+        self.files
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::from_raw_os_error(EBADF))
+    }
+
+    fn read(&mut self, fd: i64, buf: &mut [u8]) -> io::Result<usize> {
+        match fd {
+            0 => io::stdin().read(buf),
+            _ => self.file(fd)?.read(buf),
+        }
+    }
+
+    fn write(&mut self, fd: i64, bytes: &[u8]) -> io::Result<usize> {
+        match fd {
+            1 => io::stdout().write_all(bytes),
+            2 => io::stderr().write_all(bytes),
+            _ => self.file(fd)?.write_all(bytes),
+        }
+        .map(|()| bytes.len())
+    }
+
+    /// Opens `path` with the `O_*` flags of the target and returns the
+    /// lowest free fd.
+    fn open(&mut self, path: &[u8], flags: i64) -> io::Result<i64> {
+        let target = self.target;
+        let flag = |name| target.constant(name).unwrap_or_default();
+        let access = flags & 3;
+        let file = OpenOptions::new()
+            .read(access != flag("O_WRONLY"))
+            .write(access != flag("O_RDONLY"))
+            .create(flags & flag("O_CREAT") != 0)
+            .truncate(flags & flag("O_TRUNC") != 0)
+            .append(flags & flag("O_APPEND") != 0)
+            .open(String::from_utf8_lossy(path).as_ref())?;
+        let fd = (3..)
+            .find(|fd| !self.files.contains_key(fd))
+            .unwrap_or_default();
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    /// Runs the syscall `number` for `op`. Missing arguments are 0.
+    fn syscall(&mut self, op: &Op, number: i64, args: &[i64], memory: &mut SimMemory) -> i64 {
+        let syscall = self
+            .target
+            .constants()
+            .iter()
+            .find(|(name, value)| name.starts_with("SYS_") && *value == number)
+            .map(|(name, _)| *name);
+        let arg = |idx: usize| args.get(idx).copied().unwrap_or_default();
+        let result = match syscall {
+            Some("SYS_read") => match memory.access(arg(1), arg(2)) {
+                Ok(buf) => self.read(arg(0), buf),
+                Err(err) => runtime_error(op, err),
+            }
+            .map(|len| len as i64),
+            Some("SYS_write") => match memory.access(arg(1), arg(2)) {
+                Ok(bytes) => self.write(arg(0), bytes),
+                Err(err) => runtime_error(op, err),
+            }
+            .map(|len| len as i64),
+            Some("SYS_open") => match memory.c_string(arg(0)) {
+                Ok(path) => self.open(path, arg(1)),
+                Err(err) => runtime_error(op, err),
+            },
+            Some("SYS_openat") => match memory.c_string(arg(1)) {
+                Ok(path)
+                    if arg(0) == self.target.constant("AT_FDCWD").unwrap_or_default()
+                        || path.starts_with(b"/") =>
+                {
+                    self.open(path, arg(2))
                 }
+                Ok(_) => runtime_error(
+                    op,
+                    "the simulator only supports `openat` relative to AT_FDCWD",
+                ),
+                Err(err) => runtime_error(op, err),
+            },
+            Some("SYS_close") => match arg(0) {
+                0..=2 => Ok(0),
+                fd => self
+                    .files
+                    .remove(&fd)
+                    .map(|_| 0)
+                    .ok_or_else(|| io::Error::from_raw_os_error(EBADF)),
+            },
+            Some("SYS_exit") => {
+                let _ = io::stdout().flush();
+                exit(arg(0) as i32);
             }
+            Some("SYS_getpid") => Ok(i64::from(std::process::id())),
+            _ => runtime_error(
+                op,
+                format!("the simulator doesn't support syscall {number}"),
+            ),
+        };
+        result.unwrap_or_else(|err| -i64::from(err.raw_os_error().unwrap_or(EINVAL)))
+    }
+}
+
+/// Stops the simulation if `op` fails one of its `--checked` mode checks.
+fn check_arithmetic(op: &Op, lhs: i64, rhs: i64) {
+    for check in RuntimeCheck::for_kind(op.kind) {
+        if check.fails(op.kind, lhs, rhs) {
+            runtime_error(op, check.description());
         }
-        ip += 1;
     }
 }
 
-fn simulate_program(program: &[Op]) {
+/// Runs `program`. With `checked` the arithmetic ops stop the simulation with
+/// a runtime error instead of wrapping around or dividing by zero. Syscalls
+/// are emulated with the numbers of `target`.
+fn simulate_program(program: &[Op], checked: bool, target: Target) {
     let mut stack = vec![];
+    let mut memory = SimMemory::new(program);
+    let mut os = SimOs::new(target);
+    let mut return_stack: Vec<usize> = vec![];
     let mut ip = 0;
     while ip < program.len() {
         let op = &program[ip];
-        use OpValue::{IntVal, StringVal};
+        use OpValue::{BoolVal, IntVal, MemoryRegion, Proc, StringVal};
         match op.kind {
             OpKind::Push => {
-                if let Some(val) = &op.value {
+                if let Some(StringVal(string)) = &op.value {
+                    if let Some(&start) = memory.strings.get(string) {
+                        stack.push(IntVal(string.len() as i64));
+                        stack.push(IntVal(start as i64));
+                    }
+                } else if let Some(val) = &op.value {
                     stack.push(val.clone());
                 }
                 ip += 1;
             }
-            OpKind::Plus => {
+            OpKind::Plus
+            | OpKind::Minus
+            | OpKind::Mult
+            | OpKind::Div
+            | OpKind::Mod
+            | OpKind::Shl
+            | OpKind::Shr
+            | OpKind::Bor
+            | OpKind::Band
+            | OpKind::Bxor => {
                 if let Some(IntVal(a)) = stack.pop() {
                     if let Some(IntVal(b)) = stack.pop() {
-                        stack.push(IntVal(a + b));
+                        if checked {
+                            check_arithmetic(op, b, a);
+                        }
+                        if let Some(result) = evaluate_arithmetic(op.kind, b, a) {
+                            stack.push(IntVal(result));
+                        }
                     }
                 }
                 ip += 1;
             }
-            OpKind::Minus => {
+            OpKind::DivMod => {
                 if let Some(IntVal(a)) = stack.pop() {
                     if let Some(IntVal(b)) = stack.pop() {
-                        stack.push(IntVal(b - a));
+                        if checked {
+                            check_arithmetic(op, b, a);
+                        }
+                        stack.extend(evaluate_arithmetic(OpKind::Div, b, a).map(IntVal));
+                        stack.extend(evaluate_arithmetic(OpKind::Mod, b, a).map(IntVal));
                     }
                 }
                 ip += 1;
             }
-            OpKind::Mult => {
+            OpKind::Bnot => {
                 if let Some(IntVal(a)) = stack.pop() {
-                    if let Some(IntVal(b)) = stack.pop() {
-                        stack.push(IntVal(a * b));
-                    }
+                    stack.push(IntVal(!a));
                 }
                 ip += 1;
             }
-            OpKind::Div => {
-                if let Some(IntVal(a)) = stack.pop() {
-                    if let Some(IntVal(b)) = stack.pop() {
-                        stack.push(IntVal(b / a));
+            OpKind::Equals => {
+                if let Some(a) = stack.pop() {
+                    if let Some(b) = stack.pop() {
+                        stack.push(BoolVal(b == a));
                     }
                 }
                 ip += 1;
             }
-            OpKind::Equals => {
+            OpKind::NotEquals => {
                 if let Some(a) = stack.pop() {
                     if let Some(b) = stack.pop() {
-                        stack.push(IntVal((b == a).into()));
+                        stack.push(BoolVal(b != a));
                     }
                 }
                 ip += 1;
             }
             OpKind::Print => {
-                let op = stack.pop();
-                if let Some(IntVal(a)) = op {
+                let value = match stack.pop() {
+                    Some(IntVal(a)) => Some(a),
+                    Some(BoolVal(a)) => Some(a.into()),
+                    _ => None,
+                };
+                if let Some(a) = value {
                     let mut a = format!("{a}");
                     if a.len() < 20 {
                         let num_to_pad = 20 - a.len();
@@ -354,16 +985,41 @@ fn simulate_program(program: &[Op]) {
                 ip += 1;
             }
             OpKind::Write => {
-                stack.pop();
-                if let Some(IntVal(fd)) = stack.pop() {
-                    if let Some(StringVal(string)) = stack.pop() {
-                        if fd == 1 {
-                            print!("{string}");
-                        }
+                if let (Some(IntVal(fd)), Some(IntVal(ptr)), Some(IntVal(len))) =
+                    (stack.pop(), stack.pop(), stack.pop())
+                {
+                    let bytes = match memory.access(ptr, len) {
+                        Ok(bytes) => bytes,
+                        Err(err) => runtime_error(op, err),
+                    };
+                    if let Err(err) = os.write(fd, bytes) {
+                        runtime_error(op, format!("can't write to fd {fd}: {err}"));
                     }
                 }
                 ip += 1;
             }
+            OpKind::Syscall0
+            | OpKind::Syscall1
+            | OpKind::Syscall2
+            | OpKind::Syscall3
+            | OpKind::Syscall4
+            | OpKind::Syscall5
+            | OpKind::Syscall6 => {
+                let count = op.kind.syscall_args().unwrap_or_default();
+                if let Some(IntVal(number)) = stack.pop() {
+                    // Pointers are ints as well, bools are passed as 0 or 1.
+                    let args: Vec<i64> = (0..count)
+                        .filter_map(|_| match stack.pop() {
+                            Some(IntVal(arg)) => Some(arg),
+                            Some(BoolVal(arg)) => Some(i64::from(arg)),
+                            _ => None,
+                        })
+                        .collect();
+                    let result = os.syscall(op, number, &args, &mut memory);
+                    stack.push(IntVal(result));
+                }
+                ip += 1;
+            }
             OpKind::Dup => {
                 if let Some(a) = stack.pop() {
                     stack.push(a.clone());
@@ -407,8 +1063,8 @@ fn simulate_program(program: &[Op]) {
                 ip += 1;
             }
             OpKind::If | OpKind::Do => {
-                if let Some(IntVal(a)) = stack.pop() {
-                    if a == 1 {
+                if let Some(BoolVal(a)) = stack.pop() {
+                    if a {
                         ip += 1;
                     } else if let Some(IntVal(ind)) = op.value {
                         if let Ok(ind) = ind.try_into() {
@@ -417,9 +1073,79 @@ fn simulate_program(program: &[Op]) {
                     }
                 }
             }
+            OpKind::Else => {
+                if let Some(IntVal(ind)) = op.value {
+                    if let Ok(ind) = ind.try_into() {
+                        ip = ind;
+                    }
+                }
+            }
             OpKind::While => {
                 ip += 1;
             }
+            OpKind::Exit => {
+                if let Some(IntVal(code)) = stack.pop() {
+                    let _ = io::stdout().flush();
+                    exit(code as i32);
+                }
+                ip += 1;
+            }
+            OpKind::Mem => {
+                stack.push(IntVal(SIM_MEMORY_BASE as i64));
+                ip += 1;
+            }
+            OpKind::Memory => {
+                if let Some(MemoryRegion { name, .. }) = &op.value {
+                    if let Some(start) = memory.region_start(name) {
+                        stack.push(IntVal(start as i64));
+                    }
+                }
+                ip += 1;
+            }
+            OpKind::Load8 | OpKind::Load64 => {
+                let size = if op.kind == OpKind::Load8 { 1 } else { 8 };
+                if let Some(IntVal(addr)) = stack.pop() {
+                    match memory.load(addr, size) {
+                        Ok(value) => stack.push(IntVal(value)),
+                        Err(err) => runtime_error(op, err),
+                    }
+                }
+                ip += 1;
+            }
+            OpKind::Store8 | OpKind::Store64 => {
+                let size = if op.kind == OpKind::Store8 { 1 } else { 8 };
+                if let Some(IntVal(addr)) = stack.pop() {
+                    if let Some(IntVal(value)) = stack.pop() {
+                        if let Err(err) = memory.store(addr, size, value) {
+                            runtime_error(op, err);
+                        }
+                    }
+                }
+                ip += 1;
+            }
+            OpKind::Fn => {
+                // Definitions are skipped, their body only runs through `Call`.
+                if let Some(Proc { end, .. }) = &op.value {
+                    ip = end + 1;
+                }
+            }
+            OpKind::Call => {
+                if let Some(IntVal(fn_ip)) = op.value {
+                    if let Ok(fn_ip) = TryInto::<usize>::try_into(fn_ip) {
+                        // Reported at the procedure like compiled programs do.
+                        if return_stack.len() == RET_STACK_DEPTH {
+                            runtime_error(&program[fn_ip], "return stack overflow");
+                        }
+                        return_stack.push(ip + 1);
+                        ip = fn_ip + 1;
+                    }
+                }
+            }
+            OpKind::Ret => {
+                if let Some(ret_ip) = return_stack.pop() {
+                    ip = ret_ip;
+                }
+            }
             OpKind::End => {
                 if let Some(IntVal(ind)) = op.value {
                     if let Ok(ind) = ind.try_into() {
@@ -434,7 +1160,7 @@ fn simulate_program(program: &[Op]) {
             OpKind::GT => {
                 if let Some(a) = stack.pop() {
                     if let Some(b) = stack.pop() {
-                        stack.push(IntVal((b > a).into()));
+                        stack.push(BoolVal(b > a));
                     }
                 }
                 ip += 1;
@@ -442,249 +1168,78 @@ fn simulate_program(program: &[Op]) {
             OpKind::LT => {
                 if let Some(a) = stack.pop() {
                     if let Some(b) = stack.pop() {
-                        stack.push(IntVal((b < a).into()));
+                        stack.push(BoolVal(b < a));
                     }
                 }
                 ip += 1;
             }
-        }
-    }
-}
-
-fn compile_program_darwin_arm64(program: &[Op], filename: &str) {
-    let file = File::create(format!("{filename}.s"));
-    if let Ok(file) = file {
-        let mut file = LineWriter::new(file);
-        let _ = file.write(b".global _start\n");
-        let _ = file.write(b".align 2\n\n");
-        let _ = file.write(b".text\n");
-        let _ = file.write(b"print:\n");
-        let _ = file.write(b"    adrp x0, num@PAGE\n");
-        let _ = file.write(b"    add x0, x0, num@PAGEOFF\n");
-        let _ = file.write(b"    ldr   x1, [sp], #16\n");
-        let _ = file.write(b"    mov x2, #10\n");
-        let _ = file.write(b"    mov x3, #19\n");
-        let _ = file.write(b"convert_loop:\n");
-        let _ = file.write(b"    udiv x4, x1, x2\n");
-        let _ = file.write(b"    mul x5, x4, x2\n");
-        let _ = file.write(b"    sub x6, x1, x5 \n");
-        let _ = file.write(b"    and w6, w6, #0xFF\n");
-        let _ = file.write(b"    add x6, x6, #'0'\n");
-        let _ = file.write(b"    strb w6, [x0, x3]\n");
-        let _ = file.write(b"    sub x3, x3, #1\n");
-        let _ = file.write(b"    mov x1, x4\n");
-        let _ = file.write(b"    cmp x1, #0\n");
-        let _ = file.write(b"    bne convert_loop\n");
-        let _ = file.write(b"    adrp x4, num@PAGE\n");
-        let _ = file.write(b"    add x4, x4, num@PAGEOFF\n");
-        let _ = file.write(b"    mov x1, x4\n");
-        let _ = file.write(b"    mov x0, #1\n");
-        let _ = file.write(b"    mov x2, #20\n");
-        let _ = file.write(b"    mov x16, #4\n");
-        let _ = file.write(b"    svc #0x80\n");
-        let _ = file.write(b"    adrp x0, newline@PAGE\n");
-        let _ = file.write(b"    add x0, x0, newline@PAGEOFF\n");
-        let _ = file.write(b"    mov x1, x0\n");
-        let _ = file.write(b"    mov x0, #1\n");
-        let _ = file.write(b"    mov x2, #1\n");
-        let _ = file.write(b"    mov x16, #4 \n");
-        let _ = file.write(b"    svc #0x80\n");
-        let _ = file.write(b"	mov x1, #20\n");
-        let _ = file.write(b"loop:\n");
-        let _ = file.write(b"	strb wzr, [x4], #1\n");
-        let _ = file.write(b"	subs x1, x1, #1\n");
-        let _ = file.write(b"	bne loop\n");
-        let _ = file.write(b"	ret\n\n");
-        let _ = file.write(b"_start: \n");
-
-        let mut ip = 0;
-        let strings: Vec<&Op> = program
-            .iter()
-            .filter(|op| op.kind == OpKind::Push)
-            .collect();
-        while ip < program.len() {
-            let op = &program[ip];
-            use OpValue::{IntVal, StringVal};
-            match op.kind {
-                OpKind::Push => {
-                    if let Some(IntVal(val)) = &op.value {
-                        let _ = file.write(b"    // push \n");
-                        let _ = file.write(format!("    ldr x0, ={val}\n").as_bytes());
-                        let _ = file.write(b"    str x0, [sp, #-16]!\n");
-                    } else if let Some(StringVal(val)) = &op.value {
-                        let val_idx = strings
-                            .iter()
-                            .position(|op| op.value == Some(OpValue::StringVal(val.clone())));
-                        if let Some(val) = val_idx {
-                            let _ = file.write(b"    // push \n");
-                            let _ =
-                                file.write(format!("    adrp x0, string{val}@PAGE\n").as_bytes());
-                            let _ = file
-                                .write(format!("    add x0, x0, string{val}@PAGEOFF\n").as_bytes());
-                            let _ = file.write(b"    str x0, [sp, #-16]!\n");
-                        }
-                    }
-                    ip += 1;
-                }
-                OpKind::Plus => {
-                    let _ = file.write(b"    // plus \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    add x3, x0, x1\n");
-                    let _ = file.write(b"    str x3, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Minus => {
-                    let _ = file.write(b"    // minus \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    sub x3, x1, x0\n");
-                    let _ = file.write(b"    str x3, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Mult => {
-                    let _ = file.write(b"    // mult \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    mul x3, x1, x0\n");
-                    let _ = file.write(b"    str x3, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Div => {
-                    let _ = file.write(b"    // div \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    udiv x3, x1, x0\n");
-                    let _ = file.write(b"    str x3, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Equals => {
-                    let _ = file.write(b"    // equals \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    cmp x0, x1\n");
-                    let _ = file.write(b"    cset w0, EQ\n");
-                    let _ = file.write(b"    str w0, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Dup => {
-                    let _ = file.write(b"    // dup \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    str x0, [sp, #-16]!\n");
-                    let _ = file.write(b"    str x0, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Swap => {
-                    let _ = file.write(b"    // swap \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    str x0, [sp, #-16]!\n");
-                    let _ = file.write(b"    str x1, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Rot => {
-                    let _ = file.write(b"    // rot \n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    ldr x2, [sp], #16\n");
-                    let _ = file.write(b"    ldr x3, [sp], #16\n");
-                    let _ = file.write(b"    str x2, [sp, #-16]!\n");
-                    let _ = file.write(b"    str x1, [sp, #-16]!\n");
-                    let _ = file.write(b"    str x3, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::Drop => {
-                    let _ = file.write(b"    // drop \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    ip += 1;
-                }
-                OpKind::Print => {
-                    let _ = file.write(b"    // print \n");
-                    let _ = file.write(b"    bl print\n");
-                    ip += 1;
-                }
-                OpKind::Write => {
-                    let _ = file.write(b"    // write \n");
-                    let _ = file.write(b"    ldr	X2, [sp], #16\n");
-                    let _ = file.write(b"    ldr	X0, [sp], #16\n");
-                    let _ = file.write(b"    ldr	X1, [sp], #16\n");
-                    let _ = file.write(b"    mov	X16, #4\n");
-                    let _ = file.write(b"    svc	#0x80\n");
-                    ip += 1;
-                }
-                OpKind::Over => {
-                    let _ = file.write(b"    // over \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    str x1, [sp, #-16]!\n");
-                    let _ = file.write(b"    str x0, [sp, #-16]!\n");
-                    let _ = file.write(b"    str x1, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::If => {
-                    let _ = file.write(b"    // if \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    cmp x0, #0\n");
-                    if let Some(IntVal(ind)) = op.value {
-                        let _ = file.write(format!("    beq addr_{ind}\n").as_bytes());
+            OpKind::GE => {
+                if let Some(a) = stack.pop() {
+                    if let Some(b) = stack.pop() {
+                        stack.push(BoolVal(b >= a));
                     }
-                    ip += 1;
-                }
-                OpKind::While => {
-                    let _ = file.write(format!("addr_{ip}:\n").as_bytes());
-                    ip += 1;
-                }
-                OpKind::Do => {
-                    let _ = file.write(b"    // do \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    cmp x0, #0\n");
-                    if let Some(IntVal(ind)) = op.value {
-                        let _ = file.write(format!("    beq addr_{ind}\n").as_bytes());
+                }
+                ip += 1;
+            }
+            OpKind::LE => {
+                if let Some(a) = stack.pop() {
+                    if let Some(b) = stack.pop() {
+                        stack.push(BoolVal(b <= a));
                     }
-                    ip += 1;
                 }
-                OpKind::End => {
-                    if let Some(IntVal(ind)) = op.value {
-                        if let Ok(ind) = TryInto::<usize>::try_into(ind) {
-                            if ind != ip {
-                                let _ = file.write(format!("    b addr_{ind}\n").as_bytes());
-                            }
-                        }
+                ip += 1;
+            }
+            OpKind::And | OpKind::Or => {
+                if let Some(BoolVal(a)) = stack.pop() {
+                    if let Some(BoolVal(b)) = stack.pop() {
+                        let result = if op.kind == OpKind::And {
+                            b && a
+                        } else {
+                            b || a
+                        };
+                        stack.push(BoolVal(result));
                     }
-                    ip += 1;
-                    let _ = file.write(format!("addr_{ip}:\n").as_bytes());
-                }
-                OpKind::GT => {
-                    let _ = file.write(b"    // > \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    cmp x1, x0\n");
-                    let _ = file.write(b"    cset w0, GE\n");
-                    let _ = file.write(b"    str w0, [sp, #-16]!\n");
-                    ip += 1;
-                }
-                OpKind::LT => {
-                    let _ = file.write(b"    // < \n");
-                    let _ = file.write(b"    ldr x0, [sp], #16\n");
-                    let _ = file.write(b"    ldr x1, [sp], #16\n");
-                    let _ = file.write(b"    cmp x1, x0\n");
-                    let _ = file.write(b"    cset w0, LT\n");
-                    let _ = file.write(b"    str w0, [sp, #-16]!\n");
-                    ip += 1;
-                }
-            }
-            let _ = file.write(b"\n");
+                }
+                ip += 1;
+            }
+            OpKind::Not => {
+                if let Some(BoolVal(a)) = stack.pop() {
+                    stack.push(BoolVal(!a));
+                }
+                ip += 1;
+            }
+        }
+    }
+}
+
+fn run_program(filename_pre: &str, runner: Option<&str>, silence_flag: bool) {
+    let mut cmd = if let Some(runner) = runner {
+        if !silence_flag {
+            println!("[CMD] {runner} ./{filename_pre}");
+        }
+        let mut cmd = std::process::Command::new(runner);
+        cmd.arg(format!("./{filename_pre}"));
+        cmd
+    } else {
+        if !silence_flag {
+            println!("[CMD] ./{filename_pre}");
         }
-        let _ = file.write(b"    // exit syscall\n");
-        let _ = file.write(b"    mov x0, #0\n");
-        let _ = file.write(b"    mov x16, #1\n");
-        let _ = file.write(b"    svc #0x80\n\n");
-        let _ = file.write(b".data\n");
-        let _ = file.write(b"    num: .zero 20\n");
-        let _ = file.write(b"    newline: .asciz \"\\n\" \n");
-        for (idx, string) in strings.iter().enumerate() {
-            if let Some(OpValue::StringVal(val)) = &string.value {
-                let _ = file.write(format!("    string{idx}: .asciz \"{val}\" \n").as_bytes());
+        std::process::Command::new(format!("./{filename_pre}"))
+    };
+    let res = cmd.spawn();
+
+    match res.and_then(|mut res| res.wait()) {
+        // Exit like a shell does for a child killed by a signal.
+        Ok(status) => match status.signal() {
+            Some(signal) => {
+                eprintln!("ERROR: Compiled program was killed by signal {signal}");
+                exit(128 + signal);
             }
+            None => exit(status.code().unwrap_or(1)),
+        },
+        Err(err) => {
+            eprintln!("ERROR: Failed to execute compiled program: {err}");
+            exit(1);
         }
     }
 }
@@ -692,127 +1247,105 @@ fn compile_program_darwin_arm64(program: &[Op], filename: &str) {
 fn print_usage() {
     println!("Usage: rorth [OPTIONS] <SUBCOMMAND> [ARGS]");
     println!("  SUBCOMMAND:");
-    println!("    sim <file>            Simulate the program");
+    println!("    sim [OPTIONS] <file>  Simulate the program");
     println!("    com [OPTIONS] <file>  Compile the program");
     println!("      OPTIONS:");
+    println!("        -I <dir>            Add a directory to search for included files in.");
     println!("        -r                  Run the program after successful compilation");
     println!("        -s                  Silence all logging statements.");
+    println!("        --checked           Stop with a runtime error on division by zero and arithmetic overflow.");
+    println!("        --target <triple>   Compile for the given target, defaults to the host.");
+    println!("                            Supported: x86_64-unknown-linux-gnu, aarch64-unknown-linux-gnu, aarch64-apple-darwin");
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
+    let mut args = env::args().skip(1);
+    let Some(mode) = args.next() else {
         eprintln!("ERROR: You have to pass in a mode and a file path.");
         print_usage();
         exit(1);
-    }
+    };
 
-    let mode = &args[1];
-    let mut filename = &args[2];
+    let mut filename = None;
     let mut run_flag = false;
+    let mut checked = false;
     let mut silence_flag = false;
-    if args.len() > 3 {
-        if &args[2] == "-r" {
+    let mut target = Target::host();
+    let mut include_paths: Vec<PathBuf> = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "-r" {
             run_flag = true;
-        } else if &args[2] == "-s" {
+        } else if arg == "-s" {
             silence_flag = true;
-        } else {
-            eprintln!("ERROR: Unknown option: {}", &args[2]);
+        } else if arg == "--checked" {
+            checked = true;
+        } else if arg == "--target" {
+            let Some(triple) = args.next() else {
+                eprintln!("ERROR: --target expects a target triple.");
+                print_usage();
+                exit(1);
+            };
+            target = Target::from_triple(&triple);
+            if target.is_none() {
+                eprintln!("ERROR: Unsupported target: {triple}");
+                print_usage();
+                exit(1);
+            }
+        } else if arg == "-I" {
+            let Some(dir) = args.next() else {
+                eprintln!("ERROR: -I expects a directory.");
+                print_usage();
+                exit(1);
+            };
+            include_paths.push(PathBuf::from(dir));
+        } else if arg.starts_with('-') || filename.is_some() {
+            eprintln!("ERROR: Unknown option: {arg}");
             print_usage();
             exit(1);
+        } else {
+            filename = Some(arg);
         }
-        filename = &args[3];
     }
 
-    if args.len() > 4 {
-        if &args[2] != "-r" && &args[2] != "-s" {
-            eprintln!("ERROR: Unknown option: {}", &args[2]);
-            print_usage();
-            exit(1);
-        }
-
-        if &args[3] != "-r" && &args[3] != "-s" {
-            eprintln!("ERROR: Unknown option: {}", &args[3]);
-            print_usage();
-            exit(1);
-        }
-
-        if &args[2] == "-r" {
-            run_flag = true;
-        } else if &args[2] == "-s" {
-            silence_flag = true;
-        }
-
-        if &args[3] == "-r" {
-            run_flag = true;
-        } else if &args[3] == "-s" {
-            silence_flag = true;
-        }
+    let Some(filename) = filename else {
+        eprintln!("ERROR: You have to pass in a mode and a file path.");
+        print_usage();
+        exit(1);
+    };
 
-        filename = &args[4];
-    }
+    // The simulator needs the syscall numbers of a target even on hosts that
+    // can't be compiled for.
+    let syscall_target = target.unwrap_or(Target::LinuxX86_64);
 
-    let lines = parse_file(filename.to_string());
-    if let Ok(lines) = lines {
-        let mut program = parse_word_as_op(lines);
-        cross_reference_blocks(&mut program, 0);
+    let mut diagnostics = Diagnostics::default();
+    let tokens = lex_file(&filename, &include_paths, &mut diagnostics);
+    if let Ok(tokens) = tokens {
+        let mut program = parse_word_as_op(tokens, syscall_target, &mut diagnostics);
+        cross_reference_blocks(&mut program, &mut diagnostics);
+        diagnostics.exit_on_errors();
+        type_check_program(&program, &mut diagnostics);
+        diagnostics.exit_on_errors();
         if mode == "sim" {
-            simulate_program(&program);
+            simulate_program(&program, checked, syscall_target);
         } else if mode == "com" {
+            let Some(target) = target else {
+                eprintln!("ERROR: The host platform is not supported, pass a --target.");
+                exit(1);
+            };
             let filename_pre: Vec<&str> = filename.split(".rorth").collect();
             let filename_pre = filename_pre[0];
-            compile_program_darwin_arm64(&program, filename_pre);
+            let backend = target.backend();
+            compile_program(&program, filename_pre, backend.as_ref(), checked);
             if run_flag {
-                if !silence_flag {
-                    println!("[CMD] as -arch arm64 -o {filename_pre}.o {filename_pre}.s");
-                }
-                let res = std::process::Command::new("as")
-                    .arg("-arch")
-                    .arg("arm64")
-                    .arg("-o")
-                    .arg(format!("{filename_pre}.o"))
-                    .arg(format!("{filename_pre}.s"))
-                    .status();
-
-                if let Ok(as_status) = res {
-                    if as_status.success() {
-                        if !silence_flag {
-                            println!("[CMD] ld -o {filename_pre} {filename_pre}.o -lSystem -syslibroot `xcrun -sdk macosx --show-sdk-path` -e _start -arch arm64");
-                        }
-                        let res = std::process::Command::new("ld")
-                            .arg("-o")
-                            .arg(filename_pre)
-                            .arg(format!("{filename_pre}.o"))
-                            .arg("-L")
-                            .arg("/Library/Developer/CommandLineTools/SDKs/MacOSX.sdk/usr/lib")
-                            .arg("-lSystem")
-                            .arg("-syslibroot")
-                            .arg("`xcrun -sdk macosx --show-sdk-path`")
-                            .arg("-e")
-                            .arg("_start")
-                            .arg("-arch")
-                            .arg("arm64")
-                            .status();
-
-                        if let Ok(ld_status) = res {
-                            if ld_status.success() {
-                                if !silence_flag {
-                                    println!("[CMD] ./{filename_pre}");
-                                }
-                                let mut res =
-                                    std::process::Command::new(format!("./{filename_pre}")).spawn();
-
-                                if let Ok(ref mut res) = res {
-                                    let _ = res.wait();
-                                }
-
-                                if let Some(err) = res.err() {
-                                    eprintln!("ERROR: Failed to execute compiled program: {err}");
-                                }
-                            }
-                        }
-                    }
+                if !backend.assemble(filename_pre, silence_flag) {
+                    eprintln!("ERROR: Failed to assemble {filename_pre}.s");
+                    exit(1);
+                }
+                if !backend.link(filename_pre, silence_flag) {
+                    eprintln!("ERROR: Failed to link {filename_pre}");
+                    exit(1);
                 }
+                run_program(filename_pre, backend.runner(), silence_flag);
             }
         } else {
             eprintln!("ERROR: Unknown mode '{mode}'");
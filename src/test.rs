@@ -5,6 +5,15 @@ use std::{
     process::exit,
 };
 
+/// The value of a `// <key> <value>` line in the leading comment of an example.
+fn header<'a>(source: &'a str, key: &str) -> Option<&'a str> {
+    source
+        .lines()
+        .take_while(|line| line.starts_with("//"))
+        .find_map(|line| line.strip_prefix("//")?.trim().strip_prefix(key))
+        .map(str::trim)
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
@@ -39,9 +48,15 @@ fn main() {
                     if let Some(filename) = filename {
                         let filename = filename.to_str().unwrap_or("");
                         let filename = format!("./examples/{filename}");
+                        // Examples can ask for extra flags with `// flags: ...` and expect
+                        // another exit code than 0 with `// exit code: ...`.
+                        let source = fs::read_to_string(&filename).unwrap_or_default();
+                        let flags: Vec<&str> = header(&source, "flags:").unwrap_or_default().split_whitespace().collect();
+                        let exit_code = header(&source, "exit code:").and_then(|code| code.parse().ok()).unwrap_or(0);
                         println!("[INFO] Simulating: {filename}");
                         let sim_output = std::process::Command::new("target/release/rorth")
                             .arg("sim")
+                            .args(&flags)
                             .arg(&filename)
                             .output();
 
@@ -50,6 +65,7 @@ fn main() {
                             .arg("com")
                             .arg("-r")
                             .arg("-s")
+                            .args(&flags)
                             .arg(&filename)
                             .output();
 
@@ -65,8 +81,12 @@ fn main() {
                                 let com_stderr =
                                     String::from_utf8_lossy(&com_output.stderr).to_string();
                                 if record_flag {
-                                    assert!(sim_stderr.is_empty(), "[ERROR] {filename} simulation failed ❌.\nstderr: {sim_stderr}\n");
-                                    assert!(com_stderr.is_empty(), "[ERROR] {filename} compilation failed ❌.\nstderr: {com_stderr}\n");
+                                    // Expected stderr lives next to the expected stdout, most examples have none.
+                                    let expected_stderr = fs::read_to_string(format!("{filename_pre}.err")).unwrap_or_default();
+                                    assert!(sim_stderr == expected_stderr, "[ERROR] {filename} simulation failed ❌.\nstderr: {sim_stderr}\nExpected stderr: {expected_stderr}\n");
+                                    assert!(com_stderr == expected_stderr, "[ERROR] {filename} compilation failed ❌.\nstderr: {com_stderr}\nExpected stderr: {expected_stderr}\n");
+                                    assert!(sim_output.status.code() == Some(exit_code) && com_output.status.code() == Some(exit_code),
+                                        "[ERROR] {filename} failed ❌. Simulation exit code: {:?}\nCompilation exit code: {:?}\nExpected exit code: {exit_code}\n", sim_output.status.code(), com_output.status.code());
                                     assert!(sim_stdout == com_stdout, 
                                         "[ERROR] {filename} failed ❌. Simulation stdout bytes: {:?}\nSimulation stdout: \n{sim_stdout}\nCompilation stdout bytes: {:?}\nCompilation stdout: \n{com_stdout}\n", &sim_stdout, &com_stdout);
                                     let expected_filename = format!("{filename_pre}.txt");
@@ -82,7 +102,12 @@ fn main() {
                                     let record_file = File::create(format!("{filename_pre}.txt"));
                                     if let Ok(mut record_file) = record_file {
                                         let _ = record_file.write(&com_output.stdout);
-                                        let _ = record_file.write(&com_output.stderr);
+                                    }
+                                    if !com_output.stderr.is_empty() {
+                                        let record_file = File::create(format!("{filename_pre}.err"));
+                                        if let Ok(mut record_file) = record_file {
+                                            let _ = record_file.write(&com_output.stderr);
+                                        }
                                     }
                                 }
                             } else {
@@ -0,0 +1,501 @@
+use std::{fmt, mem};
+
+use strum::EnumCount;
+
+use crate::{diagnostics::Diagnostics, op_kind_word, Op, OpKind, OpValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum DataType {
+    Int,
+    Bool,
+    Ptr,
+}
+
+impl DataType {
+    /// The type `word` names in a signature.
+    pub fn from_word(word: &str) -> Option<Self> {
+        match word {
+            "int" => Some(Self::Int),
+            "bool" => Some(Self::Bool),
+            "ptr" => Some(Self::Ptr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::Bool => write!(f, "bool"),
+            Self::Ptr => write!(f, "ptr"),
+        }
+    }
+}
+
+/// The types a `fn` takes off the stack and the types it leaves on it, both
+/// with the top of the stack last.
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd)]
+pub struct Signature {
+    pub inputs: Vec<DataType>,
+    pub outputs: Vec<DataType>,
+}
+
+/// Formats types as `[int, ptr]` with the top of the stack last.
+fn format_types(types: &[DataType]) -> String {
+    let types: Vec<String> = types.iter().map(ToString::to_string).collect();
+    format!("[{}]", types.join(", "))
+}
+
+/// The types an op pops and the types it pushes in their place.
+type Effect<'a> = (&'a [DataType], &'a [DataType]);
+
+/// Every stack effect a builtin op with a fixed signature accepts. All of them
+/// take the same number of inputs.
+fn builtin_effects(kind: OpKind) -> &'static [Effect<'static>] {
+    use DataType::{Bool, Int, Ptr};
+    match kind {
+        OpKind::Plus => &[
+            (&[Int, Int], &[Int]),
+            (&[Ptr, Int], &[Ptr]),
+            (&[Int, Ptr], &[Ptr]),
+        ],
+        OpKind::Minus => &[
+            (&[Int, Int], &[Int]),
+            (&[Ptr, Int], &[Ptr]),
+            (&[Ptr, Ptr], &[Int]),
+        ],
+        OpKind::Mult
+        | OpKind::Div
+        | OpKind::Mod
+        | OpKind::Shl
+        | OpKind::Shr
+        | OpKind::Bor
+        | OpKind::Band
+        | OpKind::Bxor => &[(&[Int, Int], &[Int])],
+        OpKind::DivMod => &[(&[Int, Int], &[Int, Int])],
+        OpKind::Bnot => &[(&[Int], &[Int])],
+        OpKind::GT | OpKind::LT | OpKind::GE | OpKind::LE => {
+            &[(&[Int, Int], &[Bool]), (&[Ptr, Ptr], &[Bool])]
+        }
+        OpKind::And | OpKind::Or => &[(&[Bool, Bool], &[Bool])],
+        OpKind::Not => &[(&[Bool], &[Bool])],
+        OpKind::Write => &[(&[Int, Ptr, Int], &[])],
+        OpKind::Exit => &[(&[Int], &[])],
+        OpKind::Mem => &[(&[], &[Ptr])],
+        OpKind::Load8 | OpKind::Load64 => &[(&[Ptr], &[Int])],
+        OpKind::Store8 | OpKind::Store64 => &[(&[Int, Ptr], &[])],
+        _ => &[],
+    }
+}
+
+/// A value on the virtual stack.
+#[derive(Debug, Clone, Copy)]
+struct Value {
+    data_type: DataType,
+    /// The ip of the op that pushed the value.
+    origin: usize,
+}
+
+fn types(values: &[Value]) -> Vec<DataType> {
+    values.iter().map(|value| value.data_type).collect()
+}
+
+/// An open block together with the stack it has to be closed with.
+enum Block {
+    /// The stack after the condition was popped.
+    If(Vec<Value>),
+    /// The stack the `if` branch left and whether it ends unreachable.
+    Else {
+        then: Vec<Value>,
+        diverges: bool,
+    },
+    /// The stack before the condition was evaluated.
+    While(Vec<Value>),
+    Do(Vec<Value>),
+    /// The stack around the definition and the types the procedure returns.
+    Fn {
+        outer: Vec<Value>,
+        name: String,
+        outputs: Vec<DataType>,
+    },
+}
+
+/// Checks the stack effects of a program the way WASM validation does: every
+/// op pops the types it expects off a virtual stack and pushes the types it
+/// produces, every block has to leave the stack in a shape known up front.
+/// Like after WASM's `unreachable` the stack is polymorphic after an `exit`
+/// until the end of the enclosing block.
+struct TypeChecker<'a> {
+    program: &'a [Op],
+    stack: Vec<Value>,
+    /// Whether the current op can't be reached because an `exit` came first.
+    unreachable: bool,
+    /// The open blocks, each with whether its start was unreachable.
+    blocks: Vec<(Block, bool)>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl<'a> TypeChecker<'a> {
+    /// The name and signature of the `fn` at `fn_ip`.
+    fn procedure(&self, fn_ip: i64) -> Option<(&'a str, &'a Signature)> {
+        let program = self.program;
+        match &program.get(usize::try_from(fn_ip).ok()?)?.value {
+            Some(OpValue::Proc {
+                name, signature, ..
+            }) => Some((name, signature)),
+            _ => None,
+        }
+    }
+
+    /// How `op` is referred to in diagnostics.
+    fn describe(&self, op: &Op) -> String {
+        match (op.kind, &op.value) {
+            (OpKind::Push, Some(OpValue::IntVal(value))) => format!("`{value}`"),
+            (OpKind::Push, Some(OpValue::BoolVal(value))) => format!("`{value}`"),
+            (OpKind::Push, _) => "the string literal".to_string(),
+            (OpKind::Memory, Some(OpValue::MemoryRegion { name, .. })) => format!("`{name}`"),
+            (OpKind::Call, Some(OpValue::IntVal(fn_ip))) => {
+                let name = self.procedure(*fn_ip).map_or("?", |(name, _)| name);
+                format!("`{name}`")
+            }
+            (OpKind::Ret, _) => "`end`".to_string(),
+            (kind, _) => format!("`{}`", op_kind_word(kind).unwrap_or("?")),
+        }
+    }
+
+    /// Pushes values of `types` produced by the op at `ip`.
+    fn push(&mut self, types: &[DataType], ip: usize) {
+        self.stack.extend(types.iter().map(|&data_type| Value {
+            data_type,
+            origin: ip,
+        }));
+    }
+
+    /// Pops the top `count` values, deepest first. Reports an error and
+    /// empties the stack if it doesn't hold enough values, unless the op is
+    /// unreachable and any values would do.
+    fn pop(&mut self, op: &Op, count: usize) -> Option<Vec<Value>> {
+        if self.stack.len() < count && self.unreachable {
+            self.stack.clear();
+            return None;
+        }
+        if self.stack.len() < count {
+            let message = format!(
+                "not enough values on the stack for {}: expected {count}, found {}",
+                self.describe(op),
+                self.stack.len()
+            );
+            self.diagnostics.error(&op.loc, message);
+            self.stack.clear();
+            return None;
+        }
+        Some(self.stack.split_off(self.stack.len() - count))
+    }
+
+    /// Applies the first of `effects` whose inputs match the top of the stack.
+    fn apply(&mut self, op: &Op, ip: usize, effects: &[Effect]) {
+        let Some(&(inputs, outputs)) = effects.first() else {
+            return;
+        };
+        if let Some(args) = self.pop(op, inputs.len()) {
+            let args = types(&args);
+            let effect = effects
+                .iter()
+                .find(|(inputs, _)| *inputs == args.as_slice());
+            if let Some((_, outputs)) = effect {
+                self.push(outputs, ip);
+                return;
+            }
+            let expected: Vec<String> = effects
+                .iter()
+                .map(|(inputs, _)| format_types(inputs))
+                .collect();
+            let message = format!(
+                "invalid argument types for {}: expected {}, found {}",
+                self.describe(op),
+                expected.join(" or "),
+                format_types(&args)
+            );
+            self.diagnostics.error(&op.loc, message);
+        }
+        self.push(outputs, ip);
+    }
+
+    /// Pops the condition of an `if` or `do`.
+    fn pop_condition(&mut self, op: &Op) {
+        if let Some(args) = self.pop(op, 1) {
+            if args[0].data_type != DataType::Bool {
+                let message = format!(
+                    "invalid condition for {}: expected bool, found {}",
+                    self.describe(op),
+                    args[0].data_type
+                );
+                self.diagnostics.error(&op.loc, message);
+            }
+        }
+    }
+
+    /// Whether the stack looks like `expected`. Past an `exit` only the values
+    /// pushed since have to match the top of `expected`.
+    fn stack_matches(&self, expected: &[DataType]) -> bool {
+        let stack = types(&self.stack);
+        if self.unreachable {
+            expected.ends_with(&stack)
+        } else {
+            stack == expected
+        }
+    }
+
+    /// Reports an error unless the stack looks like `expected` and carries on
+    /// with `expected` either way.
+    fn expect_stack(&mut self, op: &Op, expected: Vec<Value>, what: &str) {
+        if !self.stack_matches(&types(&expected)) {
+            let message = format!(
+                "{what} can't change the stack: it started as {} but ends as {}",
+                format_types(&types(&expected)),
+                format_types(&types(&self.stack))
+            );
+            self.diagnostics.error(&op.loc, message);
+        }
+        self.stack = expected;
+    }
+
+    fn check_op(&mut self, op: &Op, ip: usize) {
+        // Exhaustive handling of OpKinds in the type checker.
+        const_assert!(OpKind::COUNT == 51);
+        use OpValue::{BoolVal, IntVal, Proc, StringVal};
+        match op.kind {
+            OpKind::Push => match op.value {
+                Some(IntVal(_)) => self.push(&[DataType::Int], ip),
+                Some(BoolVal(_)) => self.push(&[DataType::Bool], ip),
+                Some(StringVal(_)) => self.push(&[DataType::Int, DataType::Ptr], ip),
+                _ => {}
+            },
+            OpKind::Memory => self.push(&[DataType::Ptr], ip),
+            OpKind::Plus
+            | OpKind::Minus
+            | OpKind::Mult
+            | OpKind::Div
+            | OpKind::Mod
+            | OpKind::DivMod
+            | OpKind::Shl
+            | OpKind::Shr
+            | OpKind::Bor
+            | OpKind::Band
+            | OpKind::Bxor
+            | OpKind::Bnot
+            | OpKind::GT
+            | OpKind::LT
+            | OpKind::GE
+            | OpKind::LE
+            | OpKind::And
+            | OpKind::Or
+            | OpKind::Not
+            | OpKind::Write
+            | OpKind::Mem
+            | OpKind::Load8
+            | OpKind::Store8
+            | OpKind::Load64
+            | OpKind::Store64 => self.apply(op, ip, builtin_effects(op.kind)),
+            OpKind::Exit => {
+                self.apply(op, ip, builtin_effects(op.kind));
+                self.stack.clear();
+                self.unreachable = true;
+            }
+            OpKind::Equals | OpKind::NotEquals => {
+                if let Some(args) = self.pop(op, 2) {
+                    let args = types(&args);
+                    if args[0] != args[1] {
+                        let message = format!(
+                            "invalid argument types for {}: expected two values of the same type, found {}",
+                            self.describe(op),
+                            format_types(&args)
+                        );
+                        self.diagnostics.error(&op.loc, message);
+                    }
+                }
+                self.push(&[DataType::Bool], ip);
+            }
+            OpKind::Syscall0
+            | OpKind::Syscall1
+            | OpKind::Syscall2
+            | OpKind::Syscall3
+            | OpKind::Syscall4
+            | OpKind::Syscall5
+            | OpKind::Syscall6 => {
+                // The arguments may have any type, only the syscall number
+                // on top has to be an int.
+                let count = op.kind.syscall_args().unwrap_or_default() + 1;
+                if let Some(args) = self.pop(op, count) {
+                    if args[count - 1].data_type != DataType::Int {
+                        let message = format!(
+                            "invalid argument types for {}: expected the syscall number on top to be int, found {}",
+                            self.describe(op),
+                            format_types(&types(&args))
+                        );
+                        self.diagnostics.error(&op.loc, message);
+                    }
+                }
+                self.push(&[DataType::Int], ip);
+            }
+            OpKind::Print | OpKind::Drop => {
+                self.pop(op, 1);
+            }
+            OpKind::Dup => {
+                if let Some(args) = self.pop(op, 1) {
+                    self.stack.push(args[0]);
+                    self.push(&[args[0].data_type], ip);
+                }
+            }
+            OpKind::Swap => {
+                if let Some(args) = self.pop(op, 2) {
+                    self.stack.extend([args[1], args[0]]);
+                }
+            }
+            OpKind::Rot => {
+                if let Some(args) = self.pop(op, 3) {
+                    self.stack.extend([args[1], args[2], args[0]]);
+                }
+            }
+            OpKind::Over => {
+                if let Some(args) = self.pop(op, 2) {
+                    self.stack.extend([args[0], args[1]]);
+                    self.push(&[args[0].data_type], ip);
+                }
+            }
+            OpKind::If => {
+                self.pop_condition(op);
+                let block = Block::If(self.stack.clone());
+                self.blocks.push((block, self.unreachable));
+            }
+            OpKind::Else => {
+                if let Some((Block::If(before), unreachable)) = self.blocks.pop() {
+                    let block = Block::Else {
+                        then: mem::replace(&mut self.stack, before),
+                        diverges: self.unreachable,
+                    };
+                    self.blocks.push((block, unreachable));
+                    self.unreachable = unreachable;
+                }
+            }
+            OpKind::While => {
+                let block = Block::While(self.stack.clone());
+                self.blocks.push((block, self.unreachable));
+            }
+            OpKind::Do => {
+                self.pop_condition(op);
+                if let Some((Block::While(before), unreachable)) = self.blocks.pop() {
+                    self.expect_stack(op, before.clone(), "the condition of `while`");
+                    self.blocks.push((Block::Do(before), unreachable));
+                }
+            }
+            OpKind::End => match self.blocks.pop() {
+                Some((Block::If(before), unreachable)) => {
+                    self.expect_stack(op, before, "`if` without `else`");
+                    self.unreachable = unreachable;
+                }
+                Some((Block::Else { then, diverges }, _)) => {
+                    // A branch that ends unreachable only has to agree with
+                    // the other one on the values it pushed after the `exit`.
+                    let matches = match (diverges, self.unreachable) {
+                        (false, _) => self.stack_matches(&types(&then)),
+                        (true, false) => types(&self.stack).ends_with(&types(&then)),
+                        (true, true) => true,
+                    };
+                    if !matches {
+                        let message = format!(
+                            "both branches of `if` have to leave the same types on the stack: the `if` branch leaves {}, the `else` branch leaves {}",
+                            format_types(&types(&then)),
+                            format_types(&types(&self.stack))
+                        );
+                        self.diagnostics.error(&op.loc, message);
+                    }
+                    if !diverges {
+                        self.stack = then;
+                        self.unreachable = false;
+                    }
+                }
+                Some((Block::Do(before), unreachable)) => {
+                    self.expect_stack(op, before, "the body of `while`");
+                    self.unreachable = unreachable;
+                }
+                _ => {}
+            },
+            OpKind::Fn => {
+                if let Some(Proc {
+                    name, signature, ..
+                }) = &op.value
+                {
+                    let outer = mem::take(&mut self.stack);
+                    self.push(&signature.inputs, ip);
+                    let block = Block::Fn {
+                        outer,
+                        name: name.clone(),
+                        outputs: signature.outputs.clone(),
+                    };
+                    self.blocks.push((block, self.unreachable));
+                    self.unreachable = false;
+                }
+            }
+            OpKind::Ret => {
+                if let Some((
+                    Block::Fn {
+                        outer,
+                        name,
+                        outputs,
+                    },
+                    unreachable,
+                )) = self.blocks.pop()
+                {
+                    if !self.stack_matches(&outputs) {
+                        let message = format!(
+                            "`{name}` has to leave {} on the stack but leaves {}",
+                            format_types(&outputs),
+                            format_types(&types(&self.stack))
+                        );
+                        self.diagnostics.error(&op.loc, message);
+                    }
+                    self.stack = outer;
+                    self.unreachable = unreachable;
+                }
+            }
+            OpKind::Call => {
+                if let Some(IntVal(fn_ip)) = op.value {
+                    if let Some((_, signature)) = self.procedure(fn_ip) {
+                        self.apply(op, ip, &[(&signature.inputs, &signature.outputs)]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks that every op of `program` gets the types it expects and, if it
+/// does, warns about values still on the stack when the program ends. Has to
+/// run after `cross_reference_blocks` reported no errors.
+pub fn type_check_program(program: &[Op], diagnostics: &mut Diagnostics) {
+    let mut checker = TypeChecker {
+        program,
+        stack: vec![],
+        unreachable: false,
+        blocks: vec![],
+        diagnostics,
+    };
+    for (ip, op) in program.iter().enumerate() {
+        checker.check_op(op, ip);
+    }
+
+    if checker.diagnostics.has_errors() {
+        return;
+    }
+    for value in mem::take(&mut checker.stack) {
+        let origin = &program[value.origin];
+        let message = format!(
+            "{} leaves a value of type {} on the stack at the end of the program",
+            checker.describe(origin),
+            value.data_type
+        );
+        checker.diagnostics.warning(&origin.loc, message);
+    }
+}